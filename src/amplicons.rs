@@ -0,0 +1,197 @@
+use crate::{PrimerSet, PrimerSide};
+use anyhow::{anyhow, Context, Result};
+use debruijn::Kmer;
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+/// amplicons with fewer hits than this fraction of the median amplicon are flagged as dropouts
+const DROPOUT_FRACTION_OF_MEDIAN: f32 = 0.05;
+/// primer hits needed before a primer set can be flagged as one sided
+const MIN_BALANCE_HITS: i64 = 20;
+/// fraction of primer hits on one side above which a primer set is flagged as one sided,
+/// reads from a true primer set start at left and right primers about equally often
+const MAX_ONE_SIDED_FRACTION: f32 = 0.95;
+
+const AMPLICON_REPORT_HEADER: [&str; 10] = [
+    "primer_set",
+    "amplicon",
+    "left_hits",
+    "right_hits",
+    "total_hits",
+    "left_right_ratio",
+    "dropout",
+    "left_mean_mismatches",
+    "right_mean_mismatches",
+    "balance",
+];
+
+/// reads observed on the primers of one amplicon
+#[derive(Debug, Default)]
+pub struct AmpliconCoverage {
+    pub amplicon: String,
+    pub left_hits: i64,
+    pub right_hits: i64,
+    /// summed mismatches of the hits on the left and right primers
+    pub left_mismatches: i64,
+    pub right_mismatches: i64,
+    pub dropout: bool,
+}
+
+impl AmpliconCoverage {
+    pub fn total_hits(&self) -> i64 {
+        self.left_hits + self.right_hits
+    }
+
+    /// NaN when neither primer was observed, infinite when only the left was
+    pub fn left_right_ratio(&self) -> f32 {
+        self.left_hits as f32 / self.right_hits as f32
+    }
+
+    /// hits on the less observed primer over hits on the more observed one, 1 when balanced,
+    /// NaN without hits
+    pub fn balance(&self) -> f32 {
+        self.left_hits.min(self.right_hits) as f32 / self.left_hits.max(self.right_hits) as f32
+    }
+
+    /// mean distance of the left and right primer hits, NaN without hits
+    pub fn mean_mismatches(&self) -> (f32, f32) {
+        (
+            self.left_mismatches as f32 / self.left_hits as f32,
+            self.right_mismatches as f32 / self.right_hits as f32,
+        )
+    }
+}
+
+/// sums primer k-mer hits (both orientations, _alt primers included) for each amplicon,
+/// ordered by amplicon number. Hits on a k-mer shared by several amplicons count for each.
+pub fn amplicon_coverage<K: Kmer>(psc: &PrimerSet<K>) -> Vec<AmpliconCoverage> {
+    let mut coverage: BTreeMap<(String, u32, String), AmpliconCoverage> = BTreeMap::new();
+    let primers = psc
+        .primer_amplicons
+        .iter()
+        .flat_map(|(key, amplicons)| amplicons.iter().map(move |amplicon| (key, amplicon)));
+    for (key, (amplicon, side)) in primers {
+        let count = psc.primer_counter.get(key).copied().unwrap_or(0);
+        let mismatches = psc.primer_distances.get(key).copied().unwrap_or(0);
+        let amplicon_hits = coverage
+            .entry(amplicon_sort_key(amplicon))
+            .or_insert_with(|| AmpliconCoverage {
+                amplicon: amplicon.clone(),
+                ..Default::default()
+            });
+        match side {
+            PrimerSide::Left => {
+                amplicon_hits.left_hits += count;
+                amplicon_hits.left_mismatches += mismatches;
+            }
+            PrimerSide::Right => {
+                amplicon_hits.right_hits += count;
+                amplicon_hits.right_mismatches += mismatches;
+            }
+        }
+    }
+
+    let mut totals: Vec<i64> = coverage
+        .values()
+        .map(AmpliconCoverage::total_hits)
+        .collect();
+    totals.sort_unstable();
+    let median_hits = totals.get(totals.len() / 2).copied().unwrap_or(0);
+    let dropout_hits = median_hits as f32 * DROPOUT_FRACTION_OF_MEDIAN;
+
+    coverage
+        .into_values()
+        .map(|amplicon| {
+            let total = amplicon.total_hits();
+            AmpliconCoverage {
+                dropout: total == 0 || (total as f32) < dropout_hits,
+                ..amplicon
+            }
+        })
+        .collect()
+}
+
+/// fraction of the primer set's amplicons with at least min_hits reads on either primer
+pub fn frac_amplicons_observed<K: Kmer>(psc: &PrimerSet<K>, min_hits: i64) -> f32 {
+    let coverage = amplicon_coverage(psc);
+    let observed = coverage
+        .iter()
+        .filter(|amplicon| amplicon.total_hits() >= min_hits)
+        .count();
+    observed as f32 / coverage.len().max(1) as f32
+}
+
+/// fraction of the primer set's hits on left primers (NaN without hits), and whether the hits
+/// are dominated by one side. Hits on a k-mer shared by several amplicons count once, and not
+/// at all when it is a left primer of one amplicon and a right primer of another.
+pub fn primer_balance<K: Kmer>(psc: &PrimerSet<K>) -> (f32, bool) {
+    let (mut left_hits, mut right_hits) = (0, 0);
+    for (key, amplicons) in &psc.primer_amplicons {
+        let count = psc.primer_counter.get(key).copied().unwrap_or(0);
+        let on_side = |side| {
+            amplicons
+                .iter()
+                .all(|(_, primer_side)| *primer_side == side)
+        };
+        if on_side(PrimerSide::Left) {
+            left_hits += count;
+        } else if on_side(PrimerSide::Right) {
+            right_hits += count;
+        }
+    }
+    let total = left_hits + right_hits;
+    let frac_left = left_hits as f32 / total as f32;
+    let one_sided =
+        total >= MIN_BALANCE_HITS && frac_left.max(1.0 - frac_left) > MAX_ONE_SIDED_FRACTION;
+    (frac_left, one_sided)
+}
+
+/// sorts amplicons by their trailing number so nCoV-2019_2 comes before nCoV-2019_10
+pub(crate) fn amplicon_sort_key(amplicon: &str) -> (String, u32, String) {
+    let number_start = amplicon
+        .rfind(|c: char| !c.is_ascii_digit())
+        .map_or(0, |pos| pos + 1);
+    (
+        amplicon[..number_start].to_string(),
+        amplicon[number_start..].parse().unwrap_or(0),
+        amplicon.to_string(),
+    )
+}
+
+/// writes a tsv with the left/right primer hits of every amplicon, flagging dropouts
+pub fn write_amplicon_report<K: Kmer>(report_path: &Path, psc: &PrimerSet<K>) -> Result<()> {
+    let mut out = File::create(report_path)
+        .map(BufWriter::new)
+        .with_context(|| anyhow!("Failed to create amplicon report: {:?}", report_path))?;
+    let coverage = amplicon_coverage(psc);
+    log::info!(
+        "{} amplicon dropouts: {}/{}",
+        psc.name,
+        coverage.iter().filter(|amplicon| amplicon.dropout).count(),
+        coverage.len()
+    );
+    writeln!(out, "{}", AMPLICON_REPORT_HEADER.join("\t"))?;
+    for amplicon in coverage {
+        let (left_mean_mismatches, right_mean_mismatches) = amplicon.mean_mismatches();
+        writeln!(
+            out,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            psc.name,
+            amplicon.amplicon,
+            amplicon.left_hits,
+            amplicon.right_hits,
+            amplicon.total_hits(),
+            amplicon.left_right_ratio(),
+            amplicon.dropout,
+            left_mean_mismatches,
+            right_mean_mismatches,
+            amplicon.balance()
+        )?;
+    }
+    out.flush()?;
+    Ok(())
+}
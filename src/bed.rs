@@ -0,0 +1,161 @@
+use crate::reads::reverse_complement;
+use anyhow::{anyhow, Context, Result};
+use noodles::fasta;
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufRead, BufReader},
+    path::Path,
+};
+
+/// a primer described by one line of an ARTIC/primal-scheme style primer.bed
+#[derive(Debug)]
+struct BedPrimer {
+    chrom: String,
+    start: usize,
+    end: usize,
+    name: String,
+    /// oligo sequence, 5' to 3'
+    sequence: Option<Vec<u8>>,
+}
+
+/// true when the path looks like a primer.bed rather than a FASTA file of primer sequences
+pub fn is_bed(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("bed"))
+}
+
+/// builds primer records from a primer.bed, extracting sequences from the reference when the
+/// bed does not carry them in a 7th column. Record names keep the _LEFT/_RIGHT/_alt naming of
+/// the bed, descriptions follow the `::chrom:start-end` convention of the bundled primer sets.
+/// Like the bundled primer sets, right primers are written as the reference's plus strand (the
+/// reverse complement of the oligo), so their last bases are the oligo's 5' end.
+pub fn primer_records_from_bed(
+    bed_path: &Path,
+    reference_path: Option<&Path>,
+) -> Result<Vec<fasta::Record>> {
+    let primers = read_bed(bed_path)?;
+    let reference = if primers.iter().all(|primer| primer.sequence.is_some()) {
+        HashMap::new()
+    } else {
+        let reference_path = reference_path.ok_or_else(|| {
+            anyhow!(
+                "{:?} does not include primer sequences, --reference is required",
+                bed_path
+            )
+        })?;
+        read_reference(reference_path)?
+    };
+
+    primers
+        .into_iter()
+        .map(|primer| {
+            let sequence = match primer.sequence {
+                Some(ref oligo) if is_right(&primer.name) => reverse_complement(oligo),
+                Some(ref oligo) => oligo.clone(),
+                None => extract_sequence(&primer, &reference)?,
+            };
+            let description = format!("::{}:{}-{}", primer.chrom, primer.start, primer.end);
+            Ok(fasta::Record::new(
+                fasta::record::Definition::new(primer.name, Some(description.into_bytes())),
+                fasta::record::Sequence::from(sequence),
+            ))
+        })
+        .collect()
+}
+
+/// parses the primer lines of a bed file, skipping blank, comment and track lines
+fn read_bed(bed_path: &Path) -> Result<Vec<BedPrimer>> {
+    let bed_reader = File::open(bed_path)
+        .map(BufReader::new)
+        .with_context(|| anyhow!("Failed to open primer bed file: {:?}", bed_path))?;
+    let mut primers = Vec::new();
+    for (line_num, line) in bed_reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() || line.starts_with('#') || line.starts_with("track") {
+            continue;
+        }
+        let primer = parse_bed_line(&line)
+            .with_context(|| anyhow!("{:?} line {}: {:?}", bed_path, line_num + 1, line))?;
+        primers.push(primer);
+    }
+    Ok(primers)
+}
+
+fn parse_bed_line(line: &str) -> Result<BedPrimer> {
+    let fields: Vec<&str> = line.split('\t').map(str::trim).collect();
+    if fields.len() < 4 {
+        return Err(anyhow!(
+            "expected at least 4 columns (chrom, start, end, name)"
+        ));
+    }
+    let start: usize = fields[1].parse().context("invalid start")?;
+    let end: usize = fields[2].parse().context("invalid end")?;
+    if end <= start {
+        return Err(anyhow!("end must be greater than start"));
+    }
+    let name = fields[3].to_string();
+    if !name.to_uppercase().contains("_LEFT") && !is_right(&name) {
+        return Err(anyhow!("primer name must contain _LEFT or _RIGHT"));
+    }
+    // the 5th column, the primer pool, does not help identify the primer set
+    let strand = match fields.get(5) {
+        Some(&"+") => Some('+'),
+        Some(&"-") => Some('-'),
+        Some(&".") | Some(&"") | None => None,
+        Some(other) => return Err(anyhow!("invalid strand {:?}", other)),
+    };
+    if strand.is_some_and(|strand| (strand == '-') != is_right(&name)) {
+        log::warn!("{} strand {:?} disagrees with its name", name, strand);
+    }
+    let sequence = fields
+        .get(6)
+        .filter(|sequence| !sequence.is_empty())
+        .map(|sequence| sequence.as_bytes().to_ascii_uppercase());
+
+    Ok(BedPrimer {
+        chrom: fields[0].to_string(),
+        start,
+        end,
+        name,
+        sequence,
+    })
+}
+
+fn read_reference(reference_path: &Path) -> Result<HashMap<String, Vec<u8>>> {
+    let mut reference_reader = File::open(reference_path)
+        .map(BufReader::new)
+        .map(fasta::Reader::new)
+        .with_context(|| anyhow!("Failed to open reference file: {:?}", reference_path))?;
+    let mut reference = HashMap::new();
+    for result in reference_reader.records() {
+        let record = result?;
+        reference.insert(
+            String::from_utf8_lossy(record.name()).into_owned(),
+            record.sequence().as_ref().to_ascii_uppercase(),
+        );
+    }
+    Ok(reference)
+}
+
+fn is_right(name: &str) -> bool {
+    name.to_uppercase().contains("_RIGHT")
+}
+
+/// primer site from bed coordinates, on the reference's plus strand whatever the primer's strand
+fn extract_sequence(primer: &BedPrimer, reference: &HashMap<String, Vec<u8>>) -> Result<Vec<u8>> {
+    let chrom_seq = reference
+        .get(&primer.chrom)
+        .ok_or_else(|| anyhow!("{} not found in reference", primer.chrom))?;
+    let site = chrom_seq.get(primer.start..primer.end).ok_or_else(|| {
+        anyhow!(
+            "{} {}-{} is outside of {} ({} bp)",
+            primer.name,
+            primer.start,
+            primer.end,
+            primer.chrom,
+            chrom_seq.len()
+        )
+    })?;
+    Ok(site.to_vec())
+}
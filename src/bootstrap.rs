@@ -0,0 +1,208 @@
+use crate::{
+    amplicons, best_supported_primer_set, identify_primer_set, likelihood::ReadEndCounts,
+    CallCriteria, PrimerSet, PrimerSetCall,
+};
+use debruijn::Kmer;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rand_distr::{Binomial, Distribution};
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+/// fraction of amplicons removed in each dropout replicate
+const AMPLICON_DROPOUT_RATE: f64 = 0.5;
+
+/// read k-mer each primer set matched at one end of a read, by primer set position, None for
+/// the primer sets with no primer there. Empty when the read end was too short for a k-mer.
+pub type EndKmers<K> = Box<[Option<K>]>;
+/// read k-mers matched at the ends of a read
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ReadEnds<K> {
+    pub left: EndKmers<K>,
+    pub right: EndKmers<K>,
+    /// the ends are the starts of the mates of a read pair
+    pub paired: bool,
+}
+/// number of reads observed with each combination of read ends
+pub type ReadEndTally<K> = HashMap<ReadEnds<K>, u64>;
+
+/// fraction of bootstrap replicates reproducing the call
+#[derive(Debug, Serialize)]
+pub struct BootstrapConfidence {
+    pub replicates: usize,
+    pub seed: u64,
+    /// replicates resampling reads with replacement
+    pub read_agreement: f32,
+    /// replicates dropping a random half of the amplicons of the best supported primer set
+    pub amplicon_agreement: f32,
+}
+
+/// repeats the primer set call on resampled reads and on simulated amplicon dropouts,
+/// reporting the fraction of replicates that agree with the call, None without replicates
+pub fn bootstrap_confidence<K: Kmer>(
+    primer_set_counters: &[PrimerSet<K>],
+    read_ends: &ReadEndTally<K>,
+    call: &PrimerSetCall,
+    criteria: &CallCriteria,
+    replicates: usize,
+    seed: u64,
+) -> Option<BootstrapConfidence> {
+    if replicates == 0 {
+        return None;
+    }
+    let mut rng = StdRng::seed_from_u64(seed);
+    // sorted so that a seed always produces the same replicates
+    let mut read_end_counts: Vec<(ReadEnds<K>, u64)> = read_ends
+        .iter()
+        .map(|(ends, &count)| (ends.clone(), count))
+        .collect();
+    read_end_counts.sort_unstable();
+
+    let read_agreement = agreement(replicates, || {
+        let resampled = resample_reads(&read_end_counts, &mut rng);
+        replicate_call(primer_set_counters, &resampled, criteria) == call.primer_set
+    });
+
+    let best_supported = best_supported_primer_set(primer_set_counters);
+    let dropout_amplicons = best_supported_amplicons(best_supported);
+    let amplicon_agreement = agreement(replicates, || {
+        let dropped: HashSet<&String> = dropout_amplicons
+            .iter()
+            .filter(|_| rng.gen_bool(AMPLICON_DROPOUT_RATE))
+            .map(|&(amplicon, _)| amplicon)
+            .collect();
+        let dropped_keys: HashSet<&K> = dropout_amplicons
+            .iter()
+            .filter(|(amplicon, _)| dropped.contains(amplicon))
+            .map(|&(_, key)| key)
+            .collect();
+        let remaining = read_end_counts
+            .iter()
+            .filter(|(ends, _)| {
+                // mismatched read ends are resolved to the primer they were counted for
+                ![&ends.left, &ends.right].iter().any(|end| {
+                    best_supported
+                        .and_then(|psc| psc.matched_primer(end.get(psc.id).copied().flatten()?))
+                        .is_some_and(|key| dropped_keys.contains(&key))
+                })
+            })
+            .cloned()
+            .collect::<Vec<(ReadEnds<K>, u64)>>();
+        replicate_call(primer_set_counters, &remaining, criteria) == call.primer_set
+    });
+
+    log::info!(
+        "bootstrap agreement with {}: reads {}, amplicons {}",
+        call.primer_set,
+        read_agreement,
+        amplicon_agreement
+    );
+    Some(BootstrapConfidence {
+        replicates,
+        seed,
+        read_agreement,
+        amplicon_agreement,
+    })
+}
+
+fn agreement(replicates: usize, mut replicate_agrees: impl FnMut() -> bool) -> f32 {
+    let agreeing = (0..replicates).filter(|_| replicate_agrees()).count();
+    agreeing as f32 / replicates as f32
+}
+
+/// draws as many reads as were observed, with replacement (multinomial over read end tallies)
+fn resample_reads<K: Kmer>(
+    read_end_counts: &[(ReadEnds<K>, u64)],
+    rng: &mut StdRng,
+) -> Vec<(ReadEnds<K>, u64)> {
+    let mut reads_left: u64 = read_end_counts.iter().map(|(_, count)| count).sum();
+    let mut mass_left = reads_left as f64;
+    read_end_counts
+        .iter()
+        .map(|(ends, count)| {
+            let count = *count;
+            let p = (count as f64 / mass_left).min(1.0);
+            let drawn = if reads_left == 0 || p <= 0.0 {
+                0
+            } else {
+                Binomial::new(reads_left, p)
+                    .expect("probability is within [0, 1]")
+                    .sample(rng)
+            };
+            reads_left -= drawn;
+            mass_left -= count as f64;
+            (ends.clone(), drawn)
+        })
+        .collect()
+}
+
+/// (amplicon, primer k-mer) pairs of the primer set with the most consistent reads
+fn best_supported_amplicons<K: Kmer>(best_supported: Option<&PrimerSet<K>>) -> Vec<(&String, &K)> {
+    let amplicons: BTreeSet<(&String, &K)> = best_supported
+        .map(|psc| {
+            psc.primer_amplicons
+                .iter()
+                .flat_map(|(key, amplicons)| {
+                    amplicons.iter().map(move |(amplicon, _)| (amplicon, key))
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    amplicons.into_iter().collect()
+}
+
+/// calls the primer set for replicate read counts using fresh primer set counters
+fn replicate_call<K: Kmer>(
+    primer_set_counters: &[PrimerSet<K>],
+    read_end_counts: &[(ReadEnds<K>, u64)],
+    criteria: &CallCriteria,
+) -> String {
+    let replicate_counters = recount(primer_set_counters, read_end_counts, criteria);
+    let read_ends =
+        ReadEndCounts::from_tally(read_end_counts.iter().map(|(ends, count)| (ends, count)));
+    // at trace, replicate calls would otherwise repeat all of the logging of the original call
+    identify_primer_set(&replicate_counters, read_ends, criteria, log::Level::Trace).primer_set
+}
+
+/// fresh primer set counters counting the tallied reads, each primer set recounting the read
+/// k-mers it matched when the reads were added
+pub fn recount<K: Kmer>(
+    primer_set_counters: &[PrimerSet<K>],
+    read_end_counts: &[(ReadEnds<K>, u64)],
+    criteria: &CallCriteria,
+) -> Vec<PrimerSet<K>> {
+    primer_set_counters
+        .iter()
+        .map(|psc| {
+            let mut replicate = psc.cleared();
+            for (ends, count) in read_end_counts {
+                let count = *count as i64;
+                // ends too short for a k-mer were not counted
+                for end in [&ends.left, &ends.right]
+                    .iter()
+                    .filter_map(|end| end.get(psc.id))
+                {
+                    if let Some(key) = end {
+                        replicate.count_read_end(*key, count);
+                    } else {
+                        replicate.num_inconsistent_reads += count;
+                    }
+                }
+                if ends.paired {
+                    let [left, right] = [&ends.left, &ends.right]
+                        .map(|end| replicate.matched_primer(end.get(psc.id).copied().flatten()?));
+                    if let (Some(left), Some(right)) = (left, right) {
+                        replicate.count_pair(left, right, count);
+                    }
+                }
+            }
+            replicate.update_frac_consistent();
+            replicate.frac_amplicons_observed =
+                amplicons::frac_amplicons_observed(&replicate, criteria.min_amplicon_hits);
+            (
+                replicate.frac_left_primer_hits,
+                replicate.one_sided_primer_hits,
+            ) = amplicons::primer_balance(&replicate);
+            replicate
+        })
+        .collect()
+}
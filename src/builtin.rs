@@ -0,0 +1,73 @@
+use crate::index::amplicon_name;
+use anyhow::{anyhow, Context, Result};
+use noodles::fasta;
+use std::collections::HashSet;
+
+/// a primer set embedded in the binary from the primer_sets directory
+pub struct BuiltinScheme {
+    /// name the primer set is reported under, matching its primer_sets file
+    pub name: &'static str,
+    pub version: &'static str,
+    /// where the primer sequences were published
+    pub source: &'static str,
+    fasta: &'static str,
+}
+
+pub const BUILTIN_SCHEMES: &[BuiltinScheme] = &[
+    BuiltinScheme {
+        name: "ARTIC_v3",
+        version: "V3",
+        source: "https://github.com/artic-network/artic-ncov2019",
+        fasta: include_str!("../primer_sets/ARTIC_v3.fasta"),
+    },
+    BuiltinScheme {
+        name: "ARTIC_v4",
+        version: "V4",
+        source: "https://github.com/artic-network/artic-ncov2019",
+        fasta: include_str!("../primer_sets/ARTIC_v4.fasta"),
+    },
+    BuiltinScheme {
+        name: "Midnight_1200",
+        version: "1200",
+        source: "https://doi.org/10.1093/biomethods/bpaa014",
+        fasta: include_str!("../primer_sets/Midnight_1200.fasta"),
+    },
+    BuiltinScheme {
+        name: "neb_vss1a",
+        version: "VarSkip Short 1a",
+        source: "https://github.com/nebiolabs/VarSkip",
+        fasta: include_str!("../primer_sets/neb_vss1a.fasta"),
+    },
+    BuiltinScheme {
+        name: "neb_vss2a",
+        version: "VarSkip Short 2a",
+        source: "https://github.com/nebiolabs/VarSkip",
+        fasta: include_str!("../primer_sets/neb_vss2a.fasta"),
+    },
+    BuiltinScheme {
+        name: "neb_vsl1a",
+        version: "VarSkip Long 1a",
+        source: "https://github.com/nebiolabs/VarSkip",
+        fasta: include_str!("../primer_sets/neb_vsl1a.fasta"),
+    },
+];
+
+impl BuiltinScheme {
+    /// primer records of the embedded FASTA
+    pub fn records(&self) -> Result<Vec<fasta::Record>> {
+        fasta::Reader::new(self.fasta.as_bytes())
+            .records()
+            .collect::<Result<Vec<fasta::Record>, std::io::Error>>()
+            .with_context(|| anyhow!("Failed to read builtin primer set {}", self.name))
+    }
+
+    /// number of distinct amplicons the primers amplify
+    pub fn num_amplicons(&self) -> Result<usize> {
+        let amplicons: HashSet<String> = self
+            .records()?
+            .iter()
+            .map(|record| amplicon_name(&String::from_utf8_lossy(record.name())))
+            .collect();
+        Ok(amplicons.len())
+    }
+}
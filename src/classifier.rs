@@ -0,0 +1,517 @@
+use crate::{
+    amplicons, best_supported_primer_set, bootstrap,
+    bootstrap::{ReadEndTally, ReadEnds},
+    identify_primer_set, layout,
+    likelihood::ReadEndCounts,
+    lookup::PrimerLookup,
+    mixture,
+    neighbours::PrimerMatch,
+    offsets,
+    offsets::PrimerOffsets,
+    reads::{self, reverse_complement, Fragment, FragmentReader, Subsample},
+    stopping::{ReadsConsumed, StopReason, StoppingRule},
+    CallCriteria, PrimerSet, PrimerSetCall, PrimerSetIndex, PrimerSide,
+};
+use anyhow::Result;
+use debruijn::{dna_string::DnaString, Kmer};
+use rayon::prelude::*;
+use std::{collections::BTreeMap, io::Read, sync::Arc};
+
+/// reads parsed at a time before being shared out between threads
+const READ_CHUNK_SIZE: usize = 10_000;
+
+/// counts primer k-mers at the ends of reads added one at a time, then calls the primer set
+#[derive(Clone)]
+pub struct Classifier<K: Kmer> {
+    primer_sets: Vec<PrimerSet<K>>,
+    lookup: Arc<PrimerLookup<K>>,
+    search_window: usize,
+    read_ends: ReadEndTally<K>,
+    primer_offsets: PrimerOffsets,
+    /// number of reads of each length
+    read_lengths: BTreeMap<usize, u64>,
+    num_reads: u64,
+    stopping_rule: Option<StoppingRule>,
+    /// random subset of the reads of streams to classify and its seed
+    subsample: Option<(Subsample, u64)>,
+    /// why reading stopped early and the read agreement at the last evaluation of the call
+    stop_reason: Option<StopReason>,
+    read_agreement: Option<f32>,
+}
+
+impl<K: Kmer> Classifier<K> {
+    pub fn new(index: &PrimerSetIndex<K>) -> Self {
+        Classifier {
+            primer_sets: index.primer_sets().to_vec(),
+            lookup: Arc::clone(index.lookup()),
+            search_window: 0,
+            read_ends: ReadEndTally::new(),
+            primer_offsets: PrimerOffsets::default(),
+            read_lengths: BTreeMap::new(),
+            num_reads: 0,
+            stopping_rule: None,
+            subsample: None,
+            stop_reason: None,
+            read_agreement: None,
+        }
+    }
+
+    /// classifies a random subset of the reads of streams, drawn reproducibly from the seed
+    pub fn subsample(mut self, subsample: Subsample, seed: u64) -> Self {
+        self.subsample = Some((subsample, seed));
+        self
+    }
+
+    /// stops adding reads from streams once the rule is met, recording the reads consumed
+    pub fn stopping_rule(mut self, stopping_rule: StoppingRule) -> Self {
+        self.stopping_rule = Some(stopping_rule);
+        self
+    }
+
+    /// searches for primer k-mers anywhere within this many bases of each read end
+    pub fn search_window(mut self, search_window: usize) -> Self {
+        self.search_window = search_window;
+        self
+    }
+
+    /// number of reads added so far, short reads included
+    pub fn num_reads(&self) -> u64 {
+        self.num_reads
+    }
+
+    /// adds every read of a (possibly compressed) FASTQ, SAM, BAM or CRAM stream, pairing
+    /// interleaved mates
+    pub fn add_reads(&mut self, reads: Box<dyn Read>) -> Result<()> {
+        let fragments = self.fragments(reads, None)?;
+        self.add_fragments(fragments)
+    }
+
+    /// adds every read pair of two streams holding the first and second mates in the same order
+    pub fn add_read_pairs(&mut self, reads: Box<dyn Read>, mates: Box<dyn Read>) -> Result<()> {
+        let fragments = self.fragments(reads, Some(mates))?;
+        self.add_fragments(fragments)
+    }
+
+    /// the reads (or read pairs) of the streams, subsampled if requested
+    fn fragments(
+        &self,
+        reads: Box<dyn Read>,
+        mates: Option<Box<dyn Read>>,
+    ) -> Result<Box<dyn Iterator<Item = Result<Fragment>>>> {
+        let fragments = FragmentReader::new(reads, mates)?;
+        match self.subsample {
+            Some((subsample, seed)) => reads::subsample(fragments, subsample, seed),
+            None => Ok(Box::new(fragments)),
+        }
+    }
+
+    fn add_fragments(
+        &mut self,
+        mut fragments: Box<dyn Iterator<Item = Result<Fragment>>>,
+    ) -> Result<()> {
+        while let Some(chunk_size) = self.next_chunk_size(self.num_reads) {
+            let chunk = fragments
+                .by_ref()
+                .take(chunk_size)
+                .collect::<Result<Vec<Fragment>>>()?;
+            if chunk.is_empty() {
+                break;
+            }
+            for fragment in &chunk {
+                self.add_fragment(fragment);
+            }
+            if self.is_settled(&[]) {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// reads to parse before the stopping rule is next evaluated, None once max_reads are
+    /// consumed. Chunks end at every check_every reads so threaded and unthreaded runs stop at
+    /// the same read.
+    fn next_chunk_size(&mut self, num_reads: u64) -> Option<usize> {
+        let mut chunk_size = READ_CHUNK_SIZE as u64;
+        if let Some(rule) = &self.stopping_rule {
+            if let Some(max_reads) = rule.max_reads {
+                if num_reads >= max_reads {
+                    log::info!("stopping after --max-reads {} reads", max_reads);
+                    self.stop_reason = Some(StopReason::MaxReads);
+                    return None;
+                }
+                chunk_size = chunk_size.min(max_reads - num_reads);
+            }
+            if rule.confidence_target.is_some() {
+                let check_every = rule.check_every.max(1);
+                chunk_size = chunk_size.min(check_every - num_reads % check_every);
+            }
+        }
+        Some(chunk_size as usize)
+    }
+
+    /// evaluates the call from these reads and those of the workers every check_every reads,
+    /// true once the call is settled
+    fn is_settled(&mut self, workers: &[Classifier<K>]) -> bool {
+        let Some(rule) = self.stopping_rule else {
+            return false;
+        };
+        let Some(confidence_target) = rule.confidence_target else {
+            return false;
+        };
+        let num_reads = self.num_reads + workers.iter().map(|w| w.num_reads).sum::<u64>();
+        if num_reads % rule.check_every.max(1) != 0 {
+            return false;
+        }
+        let mut merged = self.clone();
+        for worker in workers {
+            merged.merge(worker);
+        }
+        let mut result = merged.current_result(&rule.criteria);
+        result.bootstrap(rule.bootstraps, rule.seed);
+        let read_agreement = result
+            .call
+            .bootstrap
+            .as_ref()
+            .map_or(0.0, |bootstrap| bootstrap.read_agreement);
+        self.read_agreement = Some(read_agreement);
+        log::info!(
+            "{} after {} reads, {} of bootstrap replicates agree",
+            result.call.primer_set,
+            num_reads,
+            read_agreement
+        );
+        let settled = result.call.unknown_reason.is_none() && read_agreement >= confidence_target;
+        if settled {
+            self.stop_reason = Some(StopReason::Settled);
+        }
+        settled
+    }
+
+    /// adds every read of the stream, sharing each chunk of parsed reads between threads that
+    /// count their reads separately until merged at the end. Results are identical to add_reads.
+    pub fn add_reads_threaded(
+        &mut self,
+        reads: Box<dyn Read>,
+        mates: Option<Box<dyn Read>>,
+        threads: usize,
+    ) -> Result<()>
+    where
+        K: Send + Sync,
+    {
+        let mut fragments = self.fragments(reads, mates)?;
+        if threads <= 1 {
+            return self.add_fragments(fragments);
+        }
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()?;
+        let mut workers: Vec<Classifier<K>> = (0..threads).map(|_| self.cleared()).collect();
+        loop {
+            let num_reads = self.num_reads + workers.iter().map(|w| w.num_reads).sum::<u64>();
+            let Some(chunk_size) = self.next_chunk_size(num_reads) else {
+                break;
+            };
+            let chunk = fragments
+                .by_ref()
+                .take(chunk_size)
+                .collect::<Result<Vec<Fragment>>>()?;
+            if chunk.is_empty() {
+                break;
+            }
+            let reads_per_worker = chunk.len().div_ceil(threads);
+            pool.install(|| {
+                workers
+                    .par_iter_mut()
+                    .zip(chunk.par_chunks(reads_per_worker))
+                    .for_each(|(worker, worker_reads)| {
+                        for fragment in worker_reads {
+                            worker.add_fragment(fragment);
+                        }
+                    })
+            });
+            if self.is_settled(&workers) {
+                break;
+            }
+        }
+        for worker in &workers {
+            self.merge(worker);
+        }
+        Ok(())
+    }
+
+    /// a classifier over the same primer sets with no reads added
+    fn cleared(&self) -> Self {
+        Classifier {
+            primer_sets: self.primer_sets.iter().map(PrimerSet::cleared).collect(),
+            lookup: Arc::clone(&self.lookup),
+            search_window: self.search_window,
+            read_ends: ReadEndTally::new(),
+            primer_offsets: PrimerOffsets::default(),
+            read_lengths: BTreeMap::new(),
+            num_reads: 0,
+            stopping_rule: None,
+            subsample: None,
+            stop_reason: None,
+            read_agreement: None,
+        }
+    }
+
+    /// adds the counts of a classifier over the same primer sets
+    fn merge(&mut self, other: &Classifier<K>) {
+        for (psc, other_psc) in self.primer_sets.iter_mut().zip(&other.primer_sets) {
+            psc.merge(other_psc);
+        }
+        for (ends, &count) in &other.read_ends {
+            *self.read_ends.entry(ends.clone()).or_insert(0) += count;
+        }
+        self.primer_offsets.merge(&other.primer_offsets);
+        for (&length, &count) in &other.read_lengths {
+            *self.read_lengths.entry(length).or_insert(0) += count;
+        }
+        self.num_reads += other.num_reads;
+    }
+
+    fn add_fragment(&mut self, fragment: &Fragment) {
+        match fragment {
+            Fragment::Single(sequence) => self.add_sequence(sequence),
+            Fragment::Pair(sequence, mate) => self.add_pair(sequence, mate),
+        }
+    }
+
+    /// populates counts of primers observed at the ends of one read, in sequencing orientation
+    pub fn add_sequence(&mut self, sequence: &[u8]) {
+        self.num_reads += 1;
+        *self.read_lengths.entry(sequence.len()).or_insert(0) += 1;
+        let read_seq = DnaString::from_acgt_bytes(sequence);
+        if read_seq.len() < K::k() {
+            log::warn!("skipping short read {:?}", read_seq);
+            return;
+        }
+        // extracts beginning and ending bases of each read
+        let end_keys = [PrimerSide::Left, PrimerSide::Right]
+            .map(|side| offsets::end_kmers(&read_seq, self.search_window, side));
+        self.add_end_kmers(&end_keys, false);
+    }
+
+    /// populates counts of primers observed at the 5' ends of both mates of a read pair, counted
+    /// as a single read whose right end is the start of the mate. Mates on the left and right
+    /// primers of one amplicon are counted as concordant pairs.
+    pub fn add_pair(&mut self, sequence: &[u8], mate: &[u8]) {
+        self.num_reads += 1;
+        // the fragment is at least as long as its mates unless they overlap
+        *self
+            .read_lengths
+            .entry(sequence.len() + mate.len())
+            .or_insert(0) += 1;
+        let read_seq = DnaString::from_acgt_bytes(sequence);
+        let mate_seq = DnaString::from_acgt_bytes(&reverse_complement(mate));
+        if read_seq.len() < K::k() && mate_seq.len() < K::k() {
+            log::warn!("skipping short read pair {:?} {:?}", read_seq, mate_seq);
+            return;
+        }
+        // a short mate leaves its end unexamined
+        let end_keys = [
+            (PrimerSide::Left, &read_seq),
+            (PrimerSide::Right, &mate_seq),
+        ]
+        .map(|(side, seq)| {
+            if seq.len() < K::k() {
+                Vec::new()
+            } else {
+                offsets::end_kmers(seq, self.search_window, side)
+            }
+        });
+        self.add_end_kmers(&end_keys, true);
+    }
+
+    /// counts the primers matching the k-mers of each read end, ends without k-mers are skipped
+    fn add_end_kmers(&mut self, end_keys: &[Vec<K>; 2], paired: bool) {
+        // offset of the primer nearest each read end, over all primer sets
+        let mut end_offsets: [Option<usize>; 2] = [None, None];
+        // read k-mer each primer set matched at each end, kept so that recounts agree
+        let mut end_kmers: [Vec<Option<K>>; 2] = [Vec::new(), Vec::new()];
+        // primer k-mer each primer set matched at each end
+        let mut set_primers: [Vec<Option<K>>; 2] = [
+            vec![None; self.primer_sets.len()],
+            vec![None; self.primer_sets.len()],
+        ];
+        for (end, keys) in end_keys.iter().enumerate() {
+            if keys.is_empty() {
+                continue;
+            }
+            let set_matches = self.nearest_matches(keys);
+            end_kmers[end] = vec![None; self.primer_sets.len()];
+            for (set, (psc, set_match)) in self.primer_sets.iter_mut().zip(set_matches).enumerate()
+            {
+                let offset = set_match.map_or(0, |(offset, _)| offset);
+                if set_match.is_some() {
+                    end_kmers[end][set] = Some(keys[offset]);
+                }
+                if let Some((_, PrimerMatch::Primer(primer_key, _))) = set_match {
+                    set_primers[end][set] = Some(primer_key);
+                }
+                if psc.count_match(set_match.map(|(_, primer_match)| primer_match), 1)
+                    && end_offsets[end].map_or(true, |nearest| offset < nearest)
+                {
+                    end_offsets[end] = Some(offset);
+                }
+            }
+        }
+        for (set, psc) in self.primer_sets.iter_mut().enumerate() {
+            if paired {
+                if let (Some(left), Some(right)) = (set_primers[0][set], set_primers[1][set]) {
+                    psc.count_pair(left, right, 1);
+                }
+            }
+            psc.update_frac_consistent();
+        }
+        for (side, matched) in [PrimerSide::Left, PrimerSide::Right]
+            .iter()
+            .zip(end_offsets)
+        {
+            if let Some(offset) = matched {
+                self.primer_offsets.record(*side, offset);
+            }
+        }
+        let [left, right] = end_kmers.map(Vec::into_boxed_slice);
+        *self
+            .read_ends
+            .entry(ReadEnds {
+                left,
+                right,
+                paired,
+            })
+            .or_insert(0) += 1;
+    }
+
+    /// offset and primer match nearest the read end for each primer set, looking up each
+    /// end k-mer once for all primer sets
+    fn nearest_matches(&self, keys: &[K]) -> Vec<Option<(usize, PrimerMatch<K>)>> {
+        let mut set_matches = vec![None; self.primer_sets.len()];
+        let mut num_unmatched = set_matches.len();
+        for (offset, &key) in keys.iter().enumerate() {
+            for hit in self.lookup.hits(key) {
+                let set_match = &mut set_matches[hit.primer_set as usize];
+                if set_match.is_none() {
+                    *set_match = Some((offset, hit.primer));
+                    num_unmatched -= 1;
+                }
+            }
+            if num_unmatched == 0 {
+                break;
+            }
+        }
+        set_matches
+    }
+
+    /// the call from the reads added so far, leaving the classifier to count further reads
+    pub fn current_result(&self, criteria: &CallCriteria) -> ClassificationResult<K> {
+        self.clone().finish(criteria)
+    }
+
+    /// decides which primer set was used from the reads added so far
+    pub fn finish(mut self, criteria: &CallCriteria) -> ClassificationResult<K> {
+        if self.search_window > K::k() {
+            log::info!(
+                "median primer offset from read start: {:?}, from read end: {:?}",
+                self.primer_offsets.median(PrimerSide::Left),
+                self.primer_offsets.median(PrimerSide::Right)
+            );
+        }
+        for psc in &mut self.primer_sets {
+            psc.frac_amplicons_observed =
+                amplicons::frac_amplicons_observed(psc, criteria.min_amplicon_hits);
+            (psc.frac_left_primer_hits, psc.one_sided_primer_hits) = amplicons::primer_balance(psc);
+        }
+        let call = identify_primer_set(
+            &self.primer_sets,
+            ReadEndCounts::from_tally(&self.read_ends),
+            criteria,
+            log::Level::Info,
+        );
+        if let Some(called) = self
+            .primer_sets
+            .iter()
+            .find(|psc| psc.name == call.primer_set)
+        {
+            if called.one_sided_primer_hits {
+                log::warn!(
+                    "{} of the primer hits of {} are on left primers, reads from a true primer \
+                     set should start at left and right primers about equally often",
+                    called.frac_left_primer_hits,
+                    called.name
+                );
+            }
+        }
+        let mut result = ClassificationResult {
+            call,
+            primer_sets: self.primer_sets,
+            primer_offsets: self.primer_offsets,
+            num_reads: self.num_reads,
+            read_ends: self.read_ends,
+            criteria: *criteria,
+        };
+        if self.stopping_rule.is_some() {
+            result.call.reads_consumed = Some(ReadsConsumed {
+                num_reads: result.num_reads,
+                stop_reason: self.stop_reason.unwrap_or(StopReason::EndOfReads),
+                read_agreement: self.read_agreement,
+            });
+        }
+        result.call.library_layout = result
+            .closest_primer_set()
+            .and_then(|psc| layout::library_layout(psc, &result.read_ends, &self.read_lengths));
+        result
+    }
+}
+
+/// the primer set call along with the read counts of every candidate primer set
+pub struct ClassificationResult<K: Kmer> {
+    pub call: PrimerSetCall,
+    pub primer_sets: Vec<PrimerSet<K>>,
+    /// distances of the primers found from the read ends
+    pub primer_offsets: PrimerOffsets,
+    pub num_reads: u64,
+    /// primer k-mers seen at the ends of each read, kept for resampling
+    read_ends: ReadEndTally<K>,
+    criteria: CallCriteria,
+}
+
+impl<K: Kmer> ClassificationResult<K> {
+    /// adds the fraction of bootstrap replicates reproducing the call, none with 0 replicates
+    pub fn bootstrap(&mut self, replicates: usize, seed: u64) {
+        self.call.bootstrap = bootstrap::bootstrap_confidence(
+            &self.primer_sets,
+            &self.read_ends,
+            &self.call,
+            &self.criteria,
+            replicates,
+            seed,
+        );
+    }
+
+    /// adds the proportions of the reads attributable to each primer set, for libraries
+    /// that may mix primer sets
+    pub fn mixture(&mut self) {
+        self.call.mixture = mixture::estimate_mixture(&self.primer_sets, &self.read_ends);
+    }
+
+    /// the primer sets counted again from the read k-mers kept for resampling, as bootstrap
+    /// replicates count them
+    pub fn recounted_primer_sets(&self) -> Vec<PrimerSet<K>> {
+        let read_end_counts: Vec<_> = self
+            .read_ends
+            .iter()
+            .map(|(ends, &count)| (ends.clone(), count))
+            .collect();
+        bootstrap::recount(&self.primer_sets, &read_end_counts, &self.criteria)
+    }
+
+    /// the called primer set, or the best supported candidate when the call is unknown
+    pub fn closest_primer_set(&self) -> Option<&PrimerSet<K>> {
+        self.primer_sets
+            .iter()
+            .find(|psc| psc.name == self.call.primer_set)
+            .or_else(|| best_supported_primer_set(&self.primer_sets))
+    }
+}
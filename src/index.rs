@@ -0,0 +1,272 @@
+use crate::{
+    bed,
+    builtin::{BuiltinScheme, BUILTIN_SCHEMES},
+    layout,
+    lookup::{PrimerLookup, PrimerLookupBuilder},
+    neighbours, AmpliconLengths, PrimerAmplicons, PrimerCounter, PrimerSet, PrimerSide,
+};
+use anyhow::{anyhow, Context, Result};
+use debruijn::{dna_string::DnaString, Kmer, Vmer};
+use noodles::fasta;
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::BufReader,
+    marker::PhantomData,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+/// primer k-mers of every candidate primer set, built with PrimerSetIndex::builder()
+pub struct PrimerSetIndex<K: Kmer> {
+    pub(crate) primer_sets: Vec<PrimerSet<K>>,
+    pub(crate) lookup: Arc<PrimerLookup<K>>,
+    /// substitutions allowed between read ends and primer k-mers
+    pub(crate) max_mismatches: u8,
+}
+
+impl<K: Kmer> PrimerSetIndex<K> {
+    pub fn builder() -> PrimerSetIndexBuilder<K> {
+        PrimerSetIndexBuilder {
+            sources: Vec::new(),
+            reference: None,
+            max_mismatches: 0,
+            kmer: PhantomData,
+        }
+    }
+
+    /// names of the indexed primer sets, in the order they were added
+    pub fn primer_set_names(&self) -> impl Iterator<Item = &str> {
+        self.primer_sets.iter().map(|psc| psc.name.as_str())
+    }
+
+    /// primer sets with no reads counted, the starting point of each classification
+    pub(crate) fn primer_sets(&self) -> &[PrimerSet<K>] {
+        &self.primer_sets
+    }
+
+    /// primer sets of every primer k-mer, shared by all primer sets
+    pub(crate) fn lookup(&self) -> &Arc<PrimerLookup<K>> {
+        &self.lookup
+    }
+}
+
+enum PrimerSetSource {
+    File(PathBuf),
+    Builtin(&'static BuiltinScheme),
+    Records(String, Vec<fasta::Record>),
+}
+
+/// collects primer set files (FASTA or primer.bed) or in-memory primer records to index
+pub struct PrimerSetIndexBuilder<K: Kmer> {
+    sources: Vec<PrimerSetSource>,
+    reference: Option<PathBuf>,
+    max_mismatches: u8,
+    kmer: PhantomData<K>,
+}
+
+impl<K: Kmer> PrimerSetIndexBuilder<K> {
+    /// adds a primer set from a FASTA file or primer.bed, named after the file
+    pub fn primer_set_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.sources.push(PrimerSetSource::File(path.into()));
+        self
+    }
+
+    /// adds a primer set from primer records named with _LEFT/_RIGHT
+    pub fn primer_set_records(
+        mut self,
+        name: impl Into<String>,
+        records: Vec<fasta::Record>,
+    ) -> Self {
+        self.sources
+            .push(PrimerSetSource::Records(name.into(), records));
+        self
+    }
+
+    /// adds every primer set embedded in the binary (see builtin::BUILTIN_SCHEMES)
+    pub fn builtin_primer_sets(mut self) -> Self {
+        self.sources
+            .extend(BUILTIN_SCHEMES.iter().map(PrimerSetSource::Builtin));
+        self
+    }
+
+    /// reference used to extract primer sequences from primer.bed coordinates
+    pub fn reference(mut self, path: impl Into<PathBuf>) -> Self {
+        self.reference = Some(path.into());
+        self
+    }
+
+    /// read ends within this many substitutions of a primer k-mer are also matched
+    pub fn max_mismatches(mut self, max_mismatches: u8) -> Self {
+        self.max_mismatches = max_mismatches;
+        self
+    }
+
+    /// imports the primer sets, creating primer_set_counter objects containing k-mers to search for
+    pub fn build(self) -> Result<PrimerSetIndex<K>> {
+        let reference = self.reference.as_deref();
+        let mut indexed: Vec<(String, PrimerAmplicons<K>, AmpliconLengths)> = Vec::new();
+        let mut lookup = PrimerLookupBuilder::new();
+        for source in self.sources {
+            let (name, primer_records) = match source {
+                PrimerSetSource::File(ps_filename) => (
+                    primer_set_name(&ps_filename),
+                    primer_records_from_path(&ps_filename, reference)?,
+                ),
+                PrimerSetSource::Builtin(scheme) => (scheme.name.to_string(), scheme.records()?),
+                PrimerSetSource::Records(name, records) => (name, records),
+            };
+            if indexed
+                .iter()
+                .any(|(indexed_name, ..)| *indexed_name == name)
+            {
+                log::warn!("primer set {} was added more than once", name);
+            }
+            let (primer_counts, primer_amplicons) = primer_counts_from_records(&primer_records)
+                .with_context(|| anyhow!("Failed to index primers from {}", name))?;
+            let amplicon_lengths = amplicon_lengths_from_records(&primer_records);
+            let primer_neighbours =
+                neighbours::primer_neighbours(&primer_amplicons, self.max_mismatches);
+            lookup.add_primer_set(primer_counts.keys().copied(), primer_neighbours);
+            indexed.push((name, primer_amplicons, amplicon_lengths));
+        }
+        let lookup = Arc::new(lookup.build());
+
+        let primer_sets = indexed
+            .into_iter()
+            .enumerate()
+            .map(|(id, (name, primer_amplicons, amplicon_lengths))| {
+                PrimerSet::new(
+                    name,
+                    id,
+                    primer_amplicons,
+                    Arc::new(amplicon_lengths),
+                    Arc::clone(&lookup),
+                )
+            })
+            .collect();
+
+        Ok(PrimerSetIndex {
+            primer_sets,
+            lookup,
+            max_mismatches: self.max_mismatches,
+        })
+    }
+}
+
+/// primer records of a FASTA file, or of a primer.bed with sequences extracted from the reference
+fn primer_records_from_path(
+    ps_filename: &Path,
+    reference: Option<&Path>,
+) -> Result<Vec<fasta::Record>> {
+    if bed::is_bed(ps_filename) {
+        bed::primer_records_from_bed(ps_filename, reference)
+    } else {
+        let primer_reader = File::open(ps_filename)
+            .map(BufReader::new)
+            .map(fasta::Reader::new)
+            .with_context(|| anyhow!("Failed to open primer_sets file: {:?}", ps_filename))?;
+        primer_records_from_file(primer_reader)
+    }
+    .with_context(|| anyhow!("Failed to read records from {:?}", ps_filename))
+}
+
+/// names a primer set after its file, dropping the .primer of primer.bed files
+fn primer_set_name(ps_filename: &Path) -> String {
+    let stem = ps_filename.file_stem().unwrap().to_string_lossy();
+    stem.strip_suffix(".primer").unwrap_or(&stem).to_string()
+}
+
+/// reads all primer records from a FASTA file
+fn primer_records_from_file(
+    mut primer_reader: fasta::Reader<BufReader<File>>,
+) -> Result<Vec<fasta::Record>, anyhow::Error> {
+    let records = primer_reader
+        .records()
+        .collect::<Result<Vec<fasta::Record>, std::io::Error>>()?;
+    Ok(records)
+}
+
+/// Adds a 2-bit per base k-mer representation of the 5' end of each primer (its first k bases
+/// for left primers, last k bases for right primers) to the counter hash,
+/// also recording the amplicon each primer k-mer belongs to.
+fn primer_counts_from_records<K: Kmer>(
+    records: &[fasta::Record],
+) -> Result<(PrimerCounter<K>, PrimerAmplicons<K>), anyhow::Error> {
+    let shortest = records.iter().min_by_key(|record| record.sequence().len());
+    if let Some(shortest) = shortest.filter(|record| record.sequence().len() < K::k()) {
+        return Err(anyhow!(
+            "k-mer size {} is longer than the shortest primer {} ({} nt)",
+            K::k(),
+            String::from_utf8_lossy(shortest.name()),
+            shortest.sequence().len()
+        ));
+    }
+    let mut primer_counts: PrimerCounter<K> = HashMap::new();
+    let mut primer_amplicons: PrimerAmplicons<K> = HashMap::new();
+    for record in records {
+        let primer_seq = DnaString::from_acgt_bytes(record.sequence().as_ref());
+        let record_name = String::from_utf8_lossy(record.name()).to_lowercase();
+        let (key, side): (K, PrimerSide) = if record_name.contains("left") {
+            (primer_seq.get_kmer(0), PrimerSide::Left)
+        } else if record_name.contains("right") {
+            (
+                primer_seq.get_kmer(primer_seq.len() - K::k()),
+                PrimerSide::Right,
+            )
+        } else {
+            return Err(anyhow!(
+                "Unable to identify left/right primer from {:?}",
+                record.name()
+            ));
+        };
+
+        let amplicon = amplicon_name(&String::from_utf8_lossy(record.name()));
+        for key in [key, key.rc()] {
+            if primer_counts.insert(key, 0).is_some() {
+                log::info!("Ambiguous primer: {:?}", primer_seq);
+            }
+            primer_amplicons
+                .entry(key)
+                .or_default()
+                .insert((amplicon.clone(), side));
+        }
+    }
+    Ok((primer_counts, primer_amplicons))
+}
+
+/// amplicon lengths from the ::chrom:start-end coordinates in the primer headers
+fn amplicon_lengths_from_records(records: &[fasta::Record]) -> AmpliconLengths {
+    layout::amplicon_lengths(records.iter().filter_map(|record| {
+        let name = String::from_utf8_lossy(record.name());
+        let lowercase_name = name.to_lowercase();
+        let side = if lowercase_name.contains("left") {
+            PrimerSide::Left
+        } else if lowercase_name.contains("right") {
+            PrimerSide::Right
+        } else {
+            return None;
+        };
+        let definition = format!(
+            "{} {}",
+            name,
+            String::from_utf8_lossy(record.description().unwrap_or_default())
+        );
+        Some((
+            amplicon_name(&name),
+            side,
+            layout::primer_coordinates(&definition),
+        ))
+    }))
+}
+
+/// amplicon a primer belongs to, its name up to _LEFT/_RIGHT (e.g. nCoV-2019_98_RIGHT_alt1 -> nCoV-2019_98)
+pub(crate) fn amplicon_name(primer_name: &str) -> String {
+    let upper_name = primer_name.to_ascii_uppercase();
+    let end = ["_LEFT", "_RIGHT"]
+        .iter()
+        .filter_map(|side| upper_name.find(side))
+        .min()
+        .unwrap_or(primer_name.len());
+    primer_name[..end].to_string()
+}
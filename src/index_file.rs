@@ -0,0 +1,226 @@
+//! Precompiled primer index files, written by `ampseer index` and loaded with `--index`.
+//!
+//! A file starts with a fixed header (magic bytes, format version, k-mer size and a CRC32 of
+//! the payload) followed by the zstd compressed JSON payload: primer set names, the amplicon and
+//! side of each primer k-mer, and the shared lookup including ambiguous mismatch neighbours.
+
+use crate::{
+    lookup::{PrimerHit, PrimerLookup},
+    neighbours::PrimerMatch,
+    AmpliconLengths, PrimerAmplicons, PrimerSet, PrimerSetIndex, PrimerSide,
+};
+use anyhow::{anyhow, Context, Result};
+use debruijn::Kmer;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufReader, BufWriter, Read, Write},
+    path::Path,
+    sync::Arc,
+};
+
+const MAGIC: &[u8; 8] = b"AMPSEER\x01";
+/// incremented whenever the payload changes, version 2 added amplicon lengths
+pub const FORMAT_VERSION: u32 = 2;
+const ZSTD_LEVEL: i32 = 3;
+
+struct Header {
+    format_version: u32,
+    kmer_size: usize,
+    checksum: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Payload {
+    max_mismatches: u8,
+    primer_sets: Vec<IndexedPrimerSet>,
+    /// sorted k-mer ranks of the lookup
+    keys: Vec<u64>,
+    /// start of each k-mer's hits
+    hit_starts: Vec<u32>,
+    hits: Vec<IndexedHit>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct IndexedPrimerSet {
+    name: String,
+    /// primer k-mer rank, amplicon name and side, sorted by rank, a k-mer shared by several
+    /// amplicons is listed once for each
+    primers: Vec<(u64, String, PrimerSide)>,
+    amplicon_lengths: AmpliconLengths,
+}
+
+/// a lookup hit, with no primer when the k-mer is equally close to several primers
+#[derive(Serialize, Deserialize)]
+struct IndexedHit {
+    primer_set: u32,
+    primer: Option<u64>,
+    distance: u8,
+}
+
+/// k-mer size of an index file, read from its header
+pub fn kmer_size(path: &Path) -> Result<usize> {
+    let mut reader = open(path)?;
+    Ok(read_header(&mut reader, path)?.kmer_size)
+}
+
+impl<K: Kmer> PrimerSetIndex<K> {
+    /// writes the index to a file that load reads back without re-parsing the primer sets
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let (keys, hit_starts, hits) = self.lookup.parts();
+        let payload = Payload {
+            max_mismatches: self.max_mismatches,
+            primer_sets: self
+                .primer_sets
+                .iter()
+                .map(|psc| {
+                    let mut primers: Vec<(u64, String, PrimerSide)> = psc
+                        .primer_amplicons
+                        .iter()
+                        .flat_map(|(key, amplicons)| {
+                            amplicons
+                                .iter()
+                                .map(|(amplicon, side)| (key.to_u64(), amplicon.clone(), *side))
+                        })
+                        .collect();
+                    primers.sort();
+                    IndexedPrimerSet {
+                        name: psc.name.clone(),
+                        primers,
+                        amplicon_lengths: (*psc.amplicon_lengths).clone(),
+                    }
+                })
+                .collect(),
+            keys: keys.to_vec(),
+            hit_starts: hit_starts.to_vec(),
+            hits: hits
+                .iter()
+                .map(|hit| match hit.primer {
+                    PrimerMatch::Primer(key, distance) => IndexedHit {
+                        primer_set: hit.primer_set,
+                        primer: Some(key.to_u64()),
+                        distance,
+                    },
+                    PrimerMatch::Ambiguous(distance) => IndexedHit {
+                        primer_set: hit.primer_set,
+                        primer: None,
+                        distance,
+                    },
+                })
+                .collect(),
+        };
+        let compressed = zstd::encode_all(serde_json::to_vec(&payload)?.as_slice(), ZSTD_LEVEL)?;
+
+        let mut out = File::create(path)
+            .map(BufWriter::new)
+            .with_context(|| anyhow!("Failed to create index file {:?}", path))?;
+        out.write_all(MAGIC)?;
+        out.write_all(&FORMAT_VERSION.to_le_bytes())?;
+        out.write_all(&(K::k() as u32).to_le_bytes())?;
+        out.write_all(&crc32fast::hash(&compressed).to_le_bytes())?;
+        out.write_all(&compressed)?;
+        out.flush()?;
+        Ok(())
+    }
+
+    /// loads an index written by write, checking its format version, k-mer size and checksum
+    pub fn load(path: &Path) -> Result<Self> {
+        let mut reader = open(path)?;
+        let header = read_header(&mut reader, path)?;
+        if header.kmer_size != K::k() {
+            return Err(anyhow!(
+                "index file {:?} has k-mer size {}, not {}",
+                path,
+                header.kmer_size,
+                K::k()
+            ));
+        }
+        let mut compressed = Vec::new();
+        reader.read_to_end(&mut compressed)?;
+        if crc32fast::hash(&compressed) != header.checksum {
+            return Err(anyhow!(
+                "index file {:?} is corrupt (checksum mismatch)",
+                path
+            ));
+        }
+        let payload: Payload = serde_json::from_slice(&zstd::decode_all(compressed.as_slice())?)
+            .with_context(|| anyhow!("Failed to parse index file {:?}", path))?;
+
+        let hits = payload
+            .hits
+            .into_iter()
+            .map(|hit| PrimerHit {
+                primer_set: hit.primer_set,
+                primer: match hit.primer {
+                    Some(rank) => PrimerMatch::Primer(K::from_u64(rank), hit.distance),
+                    None => PrimerMatch::Ambiguous(hit.distance),
+                },
+            })
+            .collect();
+        let lookup = Arc::new(PrimerLookup::from_parts(
+            payload.keys,
+            payload.hit_starts,
+            hits,
+        ));
+        let primer_sets = payload
+            .primer_sets
+            .into_iter()
+            .enumerate()
+            .map(|(id, indexed)| {
+                let mut primer_amplicons: PrimerAmplicons<K> = HashMap::new();
+                for (rank, amplicon, side) in indexed.primers {
+                    primer_amplicons
+                        .entry(K::from_u64(rank))
+                        .or_default()
+                        .insert((amplicon, side));
+                }
+                PrimerSet::new(
+                    indexed.name,
+                    id,
+                    primer_amplicons,
+                    Arc::new(indexed.amplicon_lengths),
+                    Arc::clone(&lookup),
+                )
+            })
+            .collect();
+        Ok(PrimerSetIndex {
+            primer_sets,
+            lookup,
+            max_mismatches: payload.max_mismatches,
+        })
+    }
+}
+
+fn open(path: &Path) -> Result<BufReader<File>> {
+    File::open(path)
+        .map(BufReader::new)
+        .with_context(|| anyhow!("Failed to open index file {:?}", path))
+}
+
+fn read_header(reader: &mut impl Read, path: &Path) -> Result<Header> {
+    let mut magic = [0; 8];
+    let mut fields = [0; 12];
+    reader
+        .read_exact(&mut magic)
+        .and_then(|_| reader.read_exact(&mut fields))
+        .ok()
+        .filter(|_| &magic == MAGIC)
+        .ok_or_else(|| anyhow!("{:?} is not an ampseer index file", path))?;
+    let field = |i: usize| u32::from_le_bytes(fields[i * 4..i * 4 + 4].try_into().unwrap());
+    let header = Header {
+        format_version: field(0),
+        kmer_size: field(1) as usize,
+        checksum: field(2),
+    };
+    if header.format_version != FORMAT_VERSION {
+        return Err(anyhow!(
+            "index file {:?} has format version {}, this ampseer reads version {}, rebuild it \
+             with `ampseer index`",
+            path,
+            header.format_version,
+            FORMAT_VERSION
+        ));
+    }
+    Ok(header)
+}
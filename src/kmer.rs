@@ -0,0 +1,81 @@
+pub use debruijn::kmer::{Kmer12, Kmer14, Kmer15, Kmer16, Kmer20, Kmer24, Kmer30, Kmer32};
+use debruijn::kmer::{KmerSize, VarIntKmer};
+
+pub const MIN_KMER_SIZE: usize = 12;
+pub const MAX_KMER_SIZE: usize = 32;
+pub const DEFAULT_KMER_SIZE: usize = 16;
+
+/// declares a debruijn Kmer type for a length that debruijn does not provide
+macro_rules! kmer_sizes {
+    ($($kmer:ident = $storage:ty, $size:ident, $k:literal;)*) => {
+        $(
+            #[derive(Debug, Hash, Copy, Clone, Ord, PartialOrd, Eq, PartialEq)]
+            pub struct $size;
+
+            impl KmerSize for $size {
+                #[inline(always)]
+                fn K() -> usize {
+                    $k
+                }
+            }
+
+            pub type $kmer = VarIntKmer<$storage, $size>;
+        )*
+    };
+}
+
+kmer_sizes! {
+    Kmer13 = u32, K13, 13;
+    Kmer17 = u64, K17, 17;
+    Kmer18 = u64, K18, 18;
+    Kmer19 = u64, K19, 19;
+    Kmer21 = u64, K21, 21;
+    Kmer22 = u64, K22, 22;
+    Kmer23 = u64, K23, 23;
+    Kmer25 = u64, K25, 25;
+    Kmer26 = u64, K26, 26;
+    Kmer27 = u64, K27, 27;
+    Kmer28 = u64, K28, 28;
+    Kmer29 = u64, K29, 29;
+}
+
+pub type Kmer31 = VarIntKmer<u64, debruijn::kmer::K31>;
+
+/// calls the generic function with the Kmer type of the requested length, e.g.
+/// `dispatch_kmer_size!(args.kmer_size, run(&args))` calls `run::<Kmer16>(&args)` for 16
+#[macro_export]
+macro_rules! dispatch_kmer_size {
+    ($k:expr, $run:ident($($arg:expr),*)) => {{
+        use $crate::kmer::*;
+        match $k {
+            12 => $run::<Kmer12>($($arg),*),
+            13 => $run::<Kmer13>($($arg),*),
+            14 => $run::<Kmer14>($($arg),*),
+            15 => $run::<Kmer15>($($arg),*),
+            16 => $run::<Kmer16>($($arg),*),
+            17 => $run::<Kmer17>($($arg),*),
+            18 => $run::<Kmer18>($($arg),*),
+            19 => $run::<Kmer19>($($arg),*),
+            20 => $run::<Kmer20>($($arg),*),
+            21 => $run::<Kmer21>($($arg),*),
+            22 => $run::<Kmer22>($($arg),*),
+            23 => $run::<Kmer23>($($arg),*),
+            24 => $run::<Kmer24>($($arg),*),
+            25 => $run::<Kmer25>($($arg),*),
+            26 => $run::<Kmer26>($($arg),*),
+            27 => $run::<Kmer27>($($arg),*),
+            28 => $run::<Kmer28>($($arg),*),
+            29 => $run::<Kmer29>($($arg),*),
+            30 => $run::<Kmer30>($($arg),*),
+            31 => $run::<Kmer31>($($arg),*),
+            32 => $run::<Kmer32>($($arg),*),
+            k => Err(anyhow::anyhow!(
+                "k-mer size {} is not between {} and {}",
+                k,
+                MIN_KMER_SIZE,
+                MAX_KMER_SIZE
+            )),
+        }
+    }};
+}
+pub use dispatch_kmer_size;
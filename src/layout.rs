@@ -0,0 +1,159 @@
+use crate::{
+    bootstrap::ReadEndTally, offsets, opposite_primers_of_one_amplicon, AmpliconLengths, PrimerSet,
+    PrimerSide,
+};
+use debruijn::Kmer;
+use serde::Serialize;
+use std::{collections::BTreeMap, fmt};
+
+/// fraction of reads with primer evidence above which a library is full length amplicons
+const FULL_LENGTH_MIN_FRACTION: f32 = 0.6;
+/// fraction of reads with primer evidence below which a library is fragmented
+const FRAGMENTED_MAX_FRACTION: f32 = 0.2;
+/// reads at least this fraction of the median amplicon length are long enough to span amplicons
+const SPANNING_READ_FRACTION: f32 = 0.8;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LibraryType {
+    /// reads start and end at the primers of an amplicon
+    FullLength,
+    /// amplicons were tagmented or sheared, so few read ends are at primers
+    Fragmented,
+    Mixed,
+}
+
+impl fmt::Display for LibraryType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            LibraryType::FullLength => "full_length",
+            LibraryType::Fragmented => "fragmented",
+            LibraryType::Mixed => "mixed",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// whether reads span whole amplicons, inferred from the primer hits of the closest primer set
+#[derive(Debug, Serialize)]
+pub struct LibraryLayout {
+    pub library_type: LibraryType,
+    /// reads with a primer of the primer set at either end
+    pub frac_reads_on_primers: f32,
+    /// reads with the left and right primers of one amplicon at their two ends
+    pub frac_both_ends_same_amplicon: f32,
+    pub median_read_length: Option<usize>,
+    /// median amplicon length from the primer coordinates, None without coordinates
+    pub median_amplicon_length: Option<usize>,
+}
+
+/// infers the library type from the read ends matching psc and the read length distribution.
+/// Reads long enough to span the amplicons should have both primers of an amplicon at their
+/// ends in full length libraries, shorter reads (or amplicons of unknown length) at least one.
+pub fn library_layout<K: Kmer>(
+    psc: &PrimerSet<K>,
+    read_ends: &ReadEndTally<K>,
+    read_lengths: &BTreeMap<usize, u64>,
+) -> Option<LibraryLayout> {
+    let mut num_reads = 0;
+    let mut on_primers = 0;
+    let mut both_ends = 0;
+    for (ends, &count) in read_ends {
+        num_reads += count;
+        let [left, right] = [&ends.left, &ends.right].map(|end| {
+            end.get(psc.id)
+                .copied()
+                .flatten()
+                .and_then(|key| psc.matched_primer(key))
+                .and_then(|key| psc.primer_amplicons.get(&key))
+        });
+        if left.is_some() || right.is_some() {
+            on_primers += count;
+        }
+        if let (Some(left), Some(right)) = (left, right) {
+            if opposite_primers_of_one_amplicon(left, right) {
+                both_ends += count;
+            }
+        }
+    }
+    if on_primers == 0 {
+        return None;
+    }
+    let frac_reads_on_primers = on_primers as f32 / num_reads as f32;
+    let frac_both_ends_same_amplicon = both_ends as f32 / num_reads as f32;
+    let median_read_length = offsets::median_of_counts(read_lengths);
+    let median_amplicon_length = median_amplicon_length(psc);
+
+    let reads_span_amplicons = match (median_read_length, median_amplicon_length) {
+        (Some(read_length), Some(amplicon_length)) => {
+            read_length as f32 >= amplicon_length as f32 * SPANNING_READ_FRACTION
+        }
+        _ => false,
+    };
+    let evidence = if reads_span_amplicons {
+        frac_both_ends_same_amplicon
+    } else {
+        frac_reads_on_primers
+    };
+    let library_type = if evidence >= FULL_LENGTH_MIN_FRACTION {
+        LibraryType::FullLength
+    } else if evidence <= FRAGMENTED_MAX_FRACTION {
+        LibraryType::Fragmented
+    } else {
+        LibraryType::Mixed
+    };
+    log::info!(
+        "{} library: {} of reads on primers, {} with both primers of an amplicon, \
+         median read length {:?}, median amplicon length {:?}",
+        library_type,
+        frac_reads_on_primers,
+        frac_both_ends_same_amplicon,
+        median_read_length,
+        median_amplicon_length
+    );
+    Some(LibraryLayout {
+        library_type,
+        frac_reads_on_primers,
+        frac_both_ends_same_amplicon,
+        median_read_length,
+        median_amplicon_length,
+    })
+}
+
+fn median_amplicon_length<K: Kmer>(psc: &PrimerSet<K>) -> Option<usize> {
+    let mut lengths: Vec<usize> = psc.amplicon_lengths.values().copied().collect();
+    lengths.sort_unstable();
+    lengths.get(lengths.len() / 2).copied()
+}
+
+/// start and end of a primer from the ::chrom:start-end suffix of its FASTA header
+pub(crate) fn primer_coordinates(definition: &str) -> Option<(usize, usize)> {
+    let location = definition.split("::").nth(1)?.split_whitespace().next()?;
+    let (start, end) = location.rsplit_once(':')?.1.split_once('-')?;
+    Some((start.parse().ok()?, end.parse().ok()?))
+}
+
+/// length of each amplicon from its leftmost left primer start to its rightmost right primer
+/// end, for amplicons with coordinates on both sides
+pub(crate) fn amplicon_lengths(
+    primers: impl Iterator<Item = (String, PrimerSide, Option<(usize, usize)>)>,
+) -> AmpliconLengths {
+    let mut spans: BTreeMap<String, (Option<usize>, Option<usize>)> = BTreeMap::new();
+    for (amplicon, side, coordinates) in primers {
+        let Some((start, end)) = coordinates else {
+            continue;
+        };
+        let (left_start, right_end) = spans.entry(amplicon).or_default();
+        match side {
+            PrimerSide::Left => *left_start = Some(left_start.map_or(start, |s| s.min(start))),
+            PrimerSide::Right => *right_end = Some(right_end.map_or(end, |e| e.max(end))),
+        }
+    }
+    spans
+        .into_iter()
+        .filter_map(|(amplicon, span)| match span {
+            (Some(start), Some(end)) if end > start => Some((amplicon, end - start)),
+            _ => None,
+        })
+        .collect()
+}
@@ -0,0 +1,616 @@
+//! Identifies the multiplex PCR primer set (amplicon scheme) used to create a sequencing
+//! library by comparing read ends with the primer k-mers of candidate primer sets.
+//!
+//! Build a [`PrimerSetIndex`], feed reads to a [`Classifier`] and read the call from the
+//! [`ClassificationResult`]:
+//!
+//! ```no_run
+//! use ampseer::{CallCriteria, Classifier, PrimerSetIndex};
+//! use debruijn::kmer::Kmer16;
+//!
+//! let index = PrimerSetIndex::<Kmer16>::builder()
+//!     .primer_set_file("primer_sets/ARTIC_v4.fasta")
+//!     .primer_set_file("primer_sets/neb_vss2a.fasta")
+//!     .build()?;
+//! let mut classifier = Classifier::new(&index);
+//! classifier.add_sequence(b"GGTAACAAACCAACCAACTTTCGATCTCTTGTAGATCTGTTCTC");
+//! let result = classifier.finish(&CallCriteria::default());
+//! println!("{} {}", result.call.primer_set, result.call.confidence);
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+//!
+//! The `report`, `samplesheet` and `watch` modules of the ampseer binary are only built with
+//! the default `cli` feature, which library users can turn off along with its dependencies.
+
+pub mod amplicons;
+mod bed;
+pub mod bootstrap;
+pub mod builtin;
+mod classifier;
+mod index;
+pub mod index_file;
+pub mod kmer;
+pub mod layout;
+pub mod likelihood;
+pub mod lookup;
+pub mod mixture;
+pub mod neighbours;
+pub mod offsets;
+pub mod overlap;
+pub mod reads;
+#[cfg(feature = "cli")]
+pub mod report;
+#[cfg(feature = "cli")]
+pub mod samplesheet;
+pub mod stopping;
+#[cfg(feature = "cli")]
+pub mod watch;
+
+pub use classifier::{ClassificationResult, Classifier};
+pub use index::{PrimerSetIndex, PrimerSetIndexBuilder};
+
+use bootstrap::BootstrapConfidence;
+use debruijn::Kmer;
+use layout::LibraryLayout;
+use likelihood::{CandidateReadEnds, Likelihoods, ReadEndCounts};
+use lookup::PrimerLookup;
+use mixture::Mixture;
+use neighbours::PrimerMatch;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    sync::Arc,
+};
+use stopping::ReadsConsumed;
+
+/// reads observed for each primer k-mer (and its reverse complement)
+pub(crate) type PrimerCounter<K> = HashMap<K, i64>;
+/// amplicon names and primer sides for each primer k-mer (and its reverse complement), more than
+/// one when primers of several amplicons share the k-mer
+pub(crate) type PrimerAmplicons<K> = HashMap<K, BTreeSet<(String, PrimerSide)>>;
+/// length of each amplicon with primer coordinates, in bases
+pub(crate) type AmpliconLengths = BTreeMap<String, usize>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum PrimerSide {
+    Left,
+    Right,
+}
+
+/// primer k-mers of one candidate primer set and the reads observed on them
+#[derive(Clone, Serialize)]
+pub struct PrimerSet<K: Kmer> {
+    pub name: String,
+    #[serde(skip)]
+    pub(crate) primer_counter: PrimerCounter<K>,
+    #[serde(skip)]
+    pub(crate) primer_amplicons: PrimerAmplicons<K>,
+    #[serde(skip)]
+    pub(crate) amplicon_lengths: Arc<AmpliconLengths>,
+    /// position of the primer set in the shared lookup
+    #[serde(skip)]
+    pub(crate) id: usize,
+    #[serde(skip)]
+    pub(crate) lookup: Arc<PrimerLookup<K>>,
+    /// summed mismatches of the read ends counted for each primer k-mer
+    #[serde(skip)]
+    pub(crate) primer_distances: PrimerCounter<K>,
+    pub num_consistent_reads: i64,
+    pub num_inconsistent_reads: i64,
+    /// consistent read ends that matched a primer with mismatches
+    pub num_mismatched_reads: i64,
+    /// read ends equally close to several primers, counted as inconsistent
+    pub num_ambiguous_reads: i64,
+    pub frac_consistent: f32,
+    /// fraction of amplicons with at least CallCriteria::min_amplicon_hits reads
+    pub frac_amplicons_observed: f32,
+    /// fraction of primer hits on left primers
+    pub frac_left_primer_hits: f32,
+    /// primer hits dominated by left or right primers, unlike the reads of a true primer set
+    pub one_sided_primer_hits: bool,
+    /// read pairs with the left and right primers of one amplicon at the starts of their mates
+    pub num_concordant_pairs: i64,
+}
+
+impl<K: Kmer> PrimerSet<K> {
+    /// a primer set with no reads counted, found at position id of the shared lookup
+    pub(crate) fn new(
+        name: String,
+        id: usize,
+        primer_amplicons: PrimerAmplicons<K>,
+        amplicon_lengths: Arc<AmpliconLengths>,
+        lookup: Arc<PrimerLookup<K>>,
+    ) -> Self {
+        PrimerSet {
+            name,
+            primer_counter: primer_amplicons.keys().map(|&key| (key, 0)).collect(),
+            primer_amplicons,
+            amplicon_lengths,
+            id,
+            lookup,
+            primer_distances: HashMap::new(),
+            num_consistent_reads: 0,
+            num_inconsistent_reads: 0,
+            num_mismatched_reads: 0,
+            num_ambiguous_reads: 0,
+            frac_consistent: 0.0,
+            frac_amplicons_observed: 0.0,
+            frac_left_primer_hits: 0.0,
+            one_sided_primer_hits: false,
+            num_concordant_pairs: 0,
+        }
+    }
+
+    /// the primer k-mer a read end matches, exactly or within the mismatch neighbourhood
+    fn match_primer(&self, key: K) -> Option<PrimerMatch<K>> {
+        self.lookup.primer_match(key, self.id)
+    }
+
+    /// the primer k-mer a read end is counted for, None when it is unmatched or ambiguous
+    fn matched_primer(&self, key: K) -> Option<K> {
+        match self.match_primer(key) {
+            Some(PrimerMatch::Primer(primer_key, _)) => Some(primer_key),
+            _ => None,
+        }
+    }
+
+    /// counts count read ends with this k-mer, returning true when they are consistent
+    fn count_read_end(&mut self, key: K, count: i64) -> bool {
+        self.count_match(self.match_primer(key), count)
+    }
+
+    /// counts count read ends already looked up, returning true when they are consistent
+    fn count_match(&mut self, primer_match: Option<PrimerMatch<K>>, count: i64) -> bool {
+        match primer_match {
+            Some(PrimerMatch::Primer(primer_key, distance)) => {
+                *self.primer_counter.entry(primer_key).or_insert(0) += count;
+                self.num_consistent_reads += count;
+                if distance > 0 {
+                    *self.primer_distances.entry(primer_key).or_insert(0) +=
+                        count * distance as i64;
+                    self.num_mismatched_reads += count;
+                }
+                true
+            }
+            Some(PrimerMatch::Ambiguous(_)) => {
+                self.num_ambiguous_reads += count;
+                self.num_inconsistent_reads += count;
+                false
+            }
+            None => {
+                self.num_inconsistent_reads += count;
+                false
+            }
+        }
+    }
+
+    /// counts count read pairs whose mates matched these primer k-mers, when they are the left
+    /// and right primers of one amplicon
+    fn count_pair(&mut self, left: K, right: K, count: i64) {
+        if let (Some(left), Some(right)) = (
+            self.primer_amplicons.get(&left),
+            self.primer_amplicons.get(&right),
+        ) {
+            if opposite_primers_of_one_amplicon(left, right) {
+                self.num_concordant_pairs += count;
+            }
+        }
+    }
+
+    fn update_frac_consistent(&mut self) {
+        self.frac_consistent = self.num_consistent_reads as f32
+            / (self.num_consistent_reads + self.num_inconsistent_reads) as f32;
+    }
+
+    /// a copy of the primer set with no reads counted
+    fn cleared(&self) -> Self {
+        PrimerSet::new(
+            self.name.clone(),
+            self.id,
+            self.primer_amplicons.clone(),
+            Arc::clone(&self.amplicon_lengths),
+            Arc::clone(&self.lookup),
+        )
+    }
+
+    /// adds the reads counted by another copy of this primer set
+    fn merge(&mut self, other: &PrimerSet<K>) {
+        for (key, count) in &other.primer_counter {
+            *self.primer_counter.entry(*key).or_insert(0) += count;
+        }
+        for (key, distance) in &other.primer_distances {
+            *self.primer_distances.entry(*key).or_insert(0) += distance;
+        }
+        self.num_consistent_reads += other.num_consistent_reads;
+        self.num_inconsistent_reads += other.num_inconsistent_reads;
+        self.num_concordant_pairs += other.num_concordant_pairs;
+        self.num_mismatched_reads += other.num_mismatched_reads;
+        self.num_ambiguous_reads += other.num_ambiguous_reads;
+        self.update_frac_consistent();
+    }
+}
+
+/// whether the primers at two read ends include the left and right primers of one amplicon
+pub(crate) fn opposite_primers_of_one_amplicon(
+    left: &BTreeSet<(String, PrimerSide)>,
+    right: &BTreeSet<(String, PrimerSide)>,
+) -> bool {
+    left.iter().any(|(left_amplicon, left_side)| {
+        right.iter().any(|(right_amplicon, right_side)| {
+            left_amplicon == right_amplicon && left_side != right_side
+        })
+    })
+}
+
+/// fraction of its amplicons a primer set must observe by default, low enough for a few hundred
+/// reads but above the stray hits of an unrelated primer set
+pub const DEFAULT_MIN_AMPLICON_FRACTION: f32 = 0.1;
+/// fraction of the most complete primer set's amplicons another primer set must observe by
+/// default, excluding kits that only contribute a few highly amplified products
+pub const DEFAULT_MIN_RELATIVE_AMPLICON_FRACTION: f32 = 0.5;
+
+/// thresholds a primer set must meet before it is considered for a call
+#[derive(Clone, Copy, Debug)]
+pub struct CallCriteria {
+    /// reads on either primer of an amplicon for it to count as observed, at least 1
+    pub min_amplicon_hits: i64,
+    /// fraction of a primer set's amplicons that must be observed
+    pub min_amplicon_fraction: f32,
+    /// fraction of the amplicons observed by the most complete primer set that must be
+    /// observed, so a few highly amplified products from another kit do not win the call
+    pub min_relative_amplicon_fraction: f32,
+}
+
+impl Default for CallCriteria {
+    fn default() -> Self {
+        CallCriteria {
+            min_amplicon_hits: 1,
+            min_amplicon_fraction: DEFAULT_MIN_AMPLICON_FRACTION,
+            min_relative_amplicon_fraction: DEFAULT_MIN_RELATIVE_AMPLICON_FRACTION,
+        }
+    }
+}
+
+/// the primer set identified for a library, or DEFAULT_PRIMER_SET with the reason why
+#[derive(Serialize)]
+pub struct PrimerSetCall {
+    pub primer_set: String,
+    pub confidence: f32,
+    pub unknown_reason: Option<String>,
+    pub unique_primer_comparison: Option<UniquePrimerComparison>,
+    pub bootstrap: Option<BootstrapConfidence>,
+    /// full length or fragmented, from the primer hits of the closest primer set
+    pub library_layout: Option<LibraryLayout>,
+    /// proportions of the reads from each primer set, estimated when requested
+    pub mixture: Option<Mixture>,
+    /// reads examined under a stopping rule and why reading stopped
+    pub reads_consumed: Option<ReadsConsumed>,
+    /// log-likelihoods and posteriors of the candidate primer sets that made the call
+    pub likelihoods: Option<Likelihoods>,
+}
+
+impl PrimerSetCall {
+    fn new(primer_set: &str, confidence: f32) -> Self {
+        PrimerSetCall {
+            primer_set: String::from(primer_set),
+            confidence,
+            unknown_reason: None,
+            unique_primer_comparison: None,
+            bootstrap: None,
+            library_layout: None,
+            mixture: None,
+            reads_consumed: None,
+            likelihoods: None,
+        }
+    }
+
+    fn unknown(reason: String) -> Self {
+        PrimerSetCall {
+            unknown_reason: Some(reason),
+            ..PrimerSetCall::new(DEFAULT_PRIMER_SET, 0.0)
+        }
+    }
+}
+
+/// read counts on the primers that differ between the two best supported primer sets
+#[derive(Serialize)]
+pub struct UniquePrimerComparison {
+    pub top_primer_set: String,
+    pub second_primer_set: String,
+    pub top_unique_reads: i64,
+    pub second_unique_reads: i64,
+    pub count_ratio: f32,
+    /// probability that the top primer set's unique primers are hit more often than the second's
+    pub probability_top_more: f64,
+}
+
+/// fraction of read ends matching the primers of a primer set that was not used, the
+/// background rate of the likelihood model
+pub(crate) const EXPECTED_NON_MATCHING_RATIO: f64 = 0.005;
+pub const DEFAULT_PRIMER_SET: &str = "unknown";
+/// posterior probability at which a primer set is called without comparing unique primers
+const MIN_CALL_POSTERIOR: f64 = 0.95;
+/// probability that one of the two most probable primer sets has its unique primers hit more
+/// often than the other at which it is called, a one sided test at the 10% level. The
+/// likelihoods already rank the primer sets, the unique primers decide between them.
+const MIN_UNIQUE_PRIMER_PROBABILITY: f64 = 0.9;
+/// read pairs with both primers of an amplicon rarely arise by chance, a primer set with this
+/// many times the concordant pairs of another is preferred when unique primers do not separate
+/// them
+const CONCORDANT_PAIR_RATIO: f32 = 2.0;
+
+/// summarizes primer set observations deciding which primer set was used, logging the
+/// decision at log_level (bootstrap replicates log at trace rather than repeat the call's log)
+fn identify_primer_set<K: Kmer>(
+    primer_set_counters: &[PrimerSet<K>],
+    read_ends: ReadEndCounts,
+    criteria: &CallCriteria,
+    log_level: log::Level,
+) -> PrimerSetCall {
+    let call = call_primer_set(primer_set_counters, read_ends, criteria, log_level);
+    if let Some(reason) = &call.unknown_reason {
+        log::log!(log_level, "primer set unknown: {}", reason);
+    }
+    call
+}
+
+fn call_primer_set<K: Kmer>(
+    primer_set_counters: &[PrimerSet<K>],
+    read_ends: ReadEndCounts,
+    criteria: &CallCriteria,
+    log_level: log::Level,
+) -> PrimerSetCall {
+    if primer_set_counters.is_empty() {
+        return PrimerSetCall::unknown("no primer sets were provided".to_string());
+    };
+
+    // a primer set with fewer reads classified but with all amplicons represented is more
+    // confident than one with more raw counts on fewer amplicons
+    let most_complete = primer_set_counters
+        .iter()
+        .map(|psc| psc.frac_amplicons_observed)
+        .fold(0.0, f32::max);
+    let (complete, incomplete): (Vec<&PrimerSet<K>>, Vec<&PrimerSet<K>>) =
+        primer_set_counters.iter().partition(|psc| {
+            psc.frac_amplicons_observed >= criteria.min_amplicon_fraction
+                && psc.frac_amplicons_observed
+                    >= most_complete * criteria.min_relative_amplicon_fraction
+        });
+    for psc in &incomplete {
+        log::log!(
+            log_level,
+            "{} excluded, observed {} of amplicons (most complete: {})",
+            psc.name,
+            psc.frac_amplicons_observed,
+            most_complete
+        );
+    }
+    if complete.is_empty() {
+        return PrimerSetCall::unknown(format!(
+            "no primer set observed at least {} of its amplicons (most complete: {})",
+            criteria.min_amplicon_fraction, most_complete
+        ));
+    }
+
+    for psc in &complete {
+        log::log!(
+            log_level.max(log::Level::Debug),
+            "{} consistent reads: {:?}",
+            psc.name,
+            psc.primer_counter
+                .iter()
+                .filter(|&(_, &count)| count > 0)
+                .collect::<HashMap<&K, &i64>>()
+        );
+        log::log!(
+            log_level,
+            "{} con/inconsistent reads: {}/{}",
+            psc.name,
+            psc.num_consistent_reads,
+            psc.num_inconsistent_reads
+        );
+    }
+    let candidates: Vec<CandidateReadEnds> = complete
+        .iter()
+        .map(|psc| CandidateReadEnds {
+            primer_set: &psc.name,
+            num_read_ends: psc.num_consistent_reads.max(0) as u64,
+            num_primers_hit: psc
+                .primer_counter
+                .values()
+                .filter(|&&count| count > 0)
+                .count() as u64,
+        })
+        .collect();
+    let likelihoods = likelihood::likelihoods(&candidates, read_ends, EXPECTED_NON_MATCHING_RATIO);
+    for score in likelihoods
+        .primer_sets
+        .iter()
+        .chain([&likelihoods.background])
+    {
+        log::log!(
+            log_level,
+            "{} log-likelihood {}, posterior {}",
+            score.primer_set,
+            score.log_likelihood,
+            score.posterior
+        );
+    }
+    let Some(best) = likelihoods.best() else {
+        return PrimerSetCall::unknown("no primer sets were provided".to_string());
+    };
+    let call = if likelihoods.background.posterior >= best.posterior {
+        PrimerSetCall::unknown(format!(
+            "{} matched {} of read ends, no more than expected by chance at the background rate {}",
+            best.primer_set,
+            complete
+                .iter()
+                .find(|psc| psc.name == best.primer_set)
+                .map_or(0.0, |psc| psc.frac_consistent),
+            EXPECTED_NON_MATCHING_RATIO
+        ))
+    } else if best.posterior >= MIN_CALL_POSTERIOR {
+        PrimerSetCall::new(&best.primer_set, best.posterior as f32)
+    } else {
+        log::log!(
+            log_level.max(log::Level::Debug),
+            "Resolving related primer sets"
+        );
+        compare_only_unique_primers(&complete, &likelihoods, log_level)
+    };
+    PrimerSetCall {
+        likelihoods: Some(likelihoods),
+        ..call
+    }
+}
+
+/// the primer set with the most reads consistent with its primers
+fn best_supported_primer_set<K: Kmer>(
+    primer_set_counters: &[PrimerSet<K>],
+) -> Option<&PrimerSet<K>> {
+    primer_set_counters
+        .iter()
+        .max_by_key(|psc| psc.num_consistent_reads)
+}
+
+/// compares the reads on the primers unique to the two most probable primer sets, which the
+/// likelihoods could not separate. A set is called when the probability that its unique primers
+/// are hit more often than the other's reaches MIN_UNIQUE_PRIMER_PROBABILITY, otherwise the
+/// set with more concordant read pairs or with balanced primer hits is preferred. The call's
+/// confidence is its posterior.
+fn compare_only_unique_primers<K: Kmer>(
+    primer_set_counters: &[&PrimerSet<K>],
+    likelihoods: &Likelihoods,
+    log_level: log::Level,
+) -> PrimerSetCall {
+    let mut ranked: Vec<&PrimerSet<K>> = primer_set_counters.to_vec();
+    ranked.sort_by(|a, b| {
+        let posterior = |psc: &PrimerSet<K>| likelihoods.posterior(&psc.name).unwrap_or(0.0);
+        posterior(b).total_cmp(&posterior(a))
+    });
+    let [top, second, ..] = ranked[..] else {
+        return PrimerSetCall::unknown(
+            "at least two primer sets are needed to compare".to_string(),
+        );
+    };
+    log::log!(
+        log_level.max(log::Level::Debug),
+        "top primer_set {:?}({:?}), second primer_set: {:?}({:?})",
+        top.name,
+        top.num_consistent_reads,
+        second.name,
+        second.num_consistent_reads
+    );
+
+    let top_ps_keys: HashSet<K> = top.primer_counter.keys().cloned().collect();
+    let second_ps_keys: HashSet<K> = second.primer_counter.keys().cloned().collect();
+    let mut uniq_top_count = 0;
+    let mut uniq_second_count = 0;
+    for uniq_key in top_ps_keys.symmetric_difference(&second_ps_keys) {
+        let top_count = top.primer_counter.get(uniq_key).unwrap_or(&0);
+        let second_count = second.primer_counter.get(uniq_key).unwrap_or(&0);
+        uniq_top_count += top_count;
+        uniq_second_count += second_count;
+        log::log!(
+            log_level.max(log::Level::Debug),
+            "uniq_key: {:?} top/second {:?}/{:?}",
+            uniq_key,
+            top_count,
+            second_count
+        );
+    }
+    let comparison = UniquePrimerComparison {
+        top_primer_set: String::from(&top.name),
+        second_primer_set: String::from(&second.name),
+        top_unique_reads: uniq_top_count,
+        second_unique_reads: uniq_second_count,
+        count_ratio: uniq_top_count as f32 / uniq_second_count as f32,
+        probability_top_more: likelihood::probability_first_more_frequent(
+            uniq_top_count.max(0) as u64,
+            uniq_second_count.max(0) as u64,
+        ),
+    };
+    let called = |psc: &PrimerSet<K>| {
+        PrimerSetCall::new(
+            &psc.name,
+            likelihoods.posterior(&psc.name).unwrap_or(0.0) as f32,
+        )
+    };
+    let call = if comparison.probability_top_more >= MIN_UNIQUE_PRIMER_PROBABILITY {
+        log::log!(
+            log_level,
+            "{} chosen over {} by reads on unique primers ({}/{}, probability {})",
+            top.name,
+            second.name,
+            uniq_top_count,
+            uniq_second_count,
+            comparison.probability_top_more
+        );
+        called(top)
+    } else if 1.0 - comparison.probability_top_more >= MIN_UNIQUE_PRIMER_PROBABILITY {
+        log::log!(
+            log_level,
+            "{} chosen over {} by reads on unique primers ({}/{}, probability {})",
+            second.name,
+            top.name,
+            uniq_second_count,
+            uniq_top_count,
+            1.0 - comparison.probability_top_more
+        );
+        called(second)
+    } else if top.num_concordant_pairs as f32
+        > second.num_concordant_pairs as f32 * CONCORDANT_PAIR_RATIO
+    {
+        log::log!(
+            log_level,
+            "{} chosen over {} by concordant read pairs ({}/{})",
+            top.name,
+            second.name,
+            top.num_concordant_pairs,
+            second.num_concordant_pairs
+        );
+        called(top)
+    } else if second.num_concordant_pairs as f32
+        > top.num_concordant_pairs as f32 * CONCORDANT_PAIR_RATIO
+    {
+        log::log!(
+            log_level,
+            "{} chosen over {} by concordant read pairs ({}/{})",
+            second.name,
+            top.name,
+            second.num_concordant_pairs,
+            top.num_concordant_pairs
+        );
+        called(second)
+    } else if second.one_sided_primer_hits && !top.one_sided_primer_hits {
+        log::log!(
+            log_level,
+            "{} chosen over one sided {}",
+            top.name,
+            second.name
+        );
+        called(top)
+    } else if top.one_sided_primer_hits && !second.one_sided_primer_hits {
+        log::log!(
+            log_level,
+            "{} chosen over one sided {}",
+            second.name,
+            top.name
+        );
+        called(second)
+    } else {
+        PrimerSetCall::unknown(format!(
+            "reads on primers unique to {} and {} ({}/{}) do not separate them, posteriors {}/{}",
+            top.name,
+            second.name,
+            uniq_top_count,
+            uniq_second_count,
+            likelihoods.posterior(&top.name).unwrap_or(0.0),
+            likelihoods.posterior(&second.name).unwrap_or(0.0)
+        ))
+    };
+    PrimerSetCall {
+        unique_primer_comparison: Some(comparison),
+        ..call
+    }
+}
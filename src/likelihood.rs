@@ -0,0 +1,203 @@
+use crate::{bootstrap::ReadEnds, DEFAULT_PRIMER_SET};
+use debruijn::Kmer;
+use serde::Serialize;
+
+/// log-likelihood of the read ends under one hypothesis and its posterior probability
+#[derive(Debug, Clone, Serialize)]
+pub struct HypothesisScore {
+    pub primer_set: String,
+    pub log_likelihood: f64,
+    pub posterior: f64,
+}
+
+/// read ends scored by the likelihood model, shared by every hypothesis
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct ReadEndCounts {
+    pub num_read_ends: u64,
+    /// read ends on the primers of any primer set
+    pub num_primer_read_ends: u64,
+}
+
+impl ReadEndCounts {
+    /// counts the ends of tallied reads, two per read
+    pub fn from_tally<'a, K: Kmer + 'a>(
+        read_end_counts: impl IntoIterator<Item = (&'a ReadEnds<K>, &'a u64)>,
+    ) -> Self {
+        let mut counts = ReadEndCounts::default();
+        for (ends, &count) in read_end_counts {
+            counts.num_read_ends += 2 * count;
+            let primer_ends = [&ends.left, &ends.right]
+                .iter()
+                .filter(|end| end.iter().any(Option::is_some))
+                .count();
+            counts.num_primer_read_ends += primer_ends as u64 * count;
+        }
+        counts
+    }
+}
+
+/// read ends consistent with the primers of one candidate primer set
+#[derive(Debug, Clone, Copy)]
+pub struct CandidateReadEnds<'a> {
+    pub primer_set: &'a str,
+    pub num_read_ends: u64,
+    /// distinct primer k-mers of the primer set found at read ends
+    pub num_primers_hit: u64,
+}
+
+impl CandidateReadEnds<'_> {
+    /// mean read ends per primer hit, read ends beyond the first on a primer are mostly copies
+    /// of the same amplicons rather than independent evidence
+    fn dispersion(&self) -> f64 {
+        if self.num_primers_hit == 0 {
+            1.0
+        } else {
+            (self.num_read_ends as f64 / self.num_primers_hit as f64).max(1.0)
+        }
+    }
+}
+
+/// scores of the hypotheses that the library was made with each candidate primer set, or with
+/// none of them, given equal prior probabilities.
+///
+/// Under the hypothesis that primer set s was used, each read end starts at one of its primers
+/// with an unknown probability theta (uniform prior, integrated out so that fitting theta is
+/// not free), otherwise it matches the primers of other primer sets at the background rate.
+/// Under the background hypothesis every primer hit is chance.
+///
+/// Reads are PCR copies of a limited number of amplicons rather than independent evidence, so
+/// the posteriors are computed from the log-likelihoods divided by the dispersion (a
+/// quasi-likelihood) of the most likely candidate. Differences between log-likelihoods do not
+/// depend on the other candidates, so neither do the posteriors of two candidates relative to
+/// each other unless a more likely candidate is added. They are still model based, the
+/// bootstrap agreement estimates how reproducible the call is.
+#[derive(Debug, Serialize)]
+pub struct Likelihoods {
+    /// probability a read end not from a primer matches primers by chance
+    pub background_rate: f64,
+    pub read_ends: ReadEndCounts,
+    /// divisor of the log-likelihoods in the posteriors, the read ends per primer hit of the
+    /// most likely candidate
+    pub dispersion: f64,
+    /// the reads came from none of the candidate primer sets
+    pub background: HypothesisScore,
+    pub primer_sets: Vec<HypothesisScore>,
+}
+
+impl Likelihoods {
+    /// the primer set with the highest posterior, None without candidates
+    pub fn best(&self) -> Option<&HypothesisScore> {
+        self.primer_sets
+            .iter()
+            .max_by(|a, b| a.posterior.total_cmp(&b.posterior))
+    }
+
+    /// the posterior of the primer set, None if it was not a candidate
+    pub fn posterior(&self, primer_set: &str) -> Option<f64> {
+        self.primer_sets
+            .iter()
+            .find(|score| score.primer_set == primer_set)
+            .map(|score| score.posterior)
+    }
+}
+
+/// scores each candidate primer set from the read ends consistent with its primers
+pub fn likelihoods(
+    candidates: &[CandidateReadEnds],
+    read_ends: ReadEndCounts,
+    background_rate: f64,
+) -> Likelihoods {
+    let (n, m) = (read_ends.num_read_ends, read_ends.num_primer_read_ends);
+    // read ends off every primer set's primers are equally likely under all hypotheses
+    let off_primers = xlogy(n.saturating_sub(m), 1.0 - background_rate);
+    let mut log_likelihoods: Vec<f64> = candidates
+        .iter()
+        .map(|candidate| {
+            let c = candidate.num_read_ends.min(n);
+            // integral over theta of theta^c (1 - theta)^(n - c)
+            let on_primers = -((n + 1) as f64).ln() - ln_binomial(n, c);
+            on_primers + xlogy(m.saturating_sub(c), background_rate) + off_primers
+        })
+        .collect();
+    let dispersion = candidates
+        .iter()
+        .zip(&log_likelihoods)
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map_or(1.0, |(candidate, _)| candidate.dispersion());
+    log_likelihoods.push(xlogy(m, background_rate) + off_primers);
+
+    let posteriors = posteriors(
+        &log_likelihoods
+            .iter()
+            .map(|log_likelihood| log_likelihood / dispersion)
+            .collect::<Vec<f64>>(),
+    );
+    let score = |primer_set: &str, i: usize| HypothesisScore {
+        primer_set: primer_set.to_string(),
+        log_likelihood: log_likelihoods[i],
+        posterior: posteriors[i],
+    };
+    Likelihoods {
+        background_rate,
+        read_ends,
+        dispersion,
+        background: score(DEFAULT_PRIMER_SET, candidates.len()),
+        primer_sets: candidates
+            .iter()
+            .enumerate()
+            .map(|(i, candidate)| score(candidate.primer_set, i))
+            .collect(),
+    }
+}
+
+/// probability that reads land on the first of two groups of primers more often than on the
+/// second, given first and second reads on them and a uniform prior on the first group's share:
+/// P(Beta(first + 1, second + 1) > 1/2), which equals P(Binomial(first + second + 1, 1/2) <= first)
+pub fn probability_first_more_frequent(first: u64, second: u64) -> f64 {
+    let n = first + second + 1;
+    let log_terms: Vec<f64> = (0..=first)
+        .map(|k| ln_binomial(n, k) - n as f64 * std::f64::consts::LN_2)
+        .collect();
+    let max = log_terms.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    let total: f64 = log_terms.iter().map(|term| (term - max).exp()).sum();
+    (max + total.ln()).exp().min(1.0)
+}
+
+/// count * ln(probability), 0 for no observations even when the probability is 0
+fn xlogy(count: u64, probability: f64) -> f64 {
+    if count == 0 {
+        0.0
+    } else {
+        count as f64 * probability.ln()
+    }
+}
+
+/// ln(n choose k)
+fn ln_binomial(n: u64, k: u64) -> f64 {
+    ln_factorial(n) - ln_factorial(k) - ln_factorial(n - k)
+}
+
+/// ln(n!), summed for small n and from Stirling's series otherwise
+fn ln_factorial(n: u64) -> f64 {
+    if n < 20 {
+        return (2..=n).map(|i| (i as f64).ln()).sum();
+    }
+    let x = (n + 1) as f64;
+    (x - 0.5) * x.ln() - x + 0.5 * (2.0 * std::f64::consts::PI).ln() + 1.0 / (12.0 * x)
+        - 1.0 / (360.0 * x.powi(3))
+        + 1.0 / (1260.0 * x.powi(5))
+}
+
+/// posterior probabilities from log-likelihoods with equal priors
+fn posteriors(log_likelihoods: &[f64]) -> Vec<f64> {
+    let max = log_likelihoods
+        .iter()
+        .copied()
+        .fold(f64::NEG_INFINITY, f64::max);
+    let weights: Vec<f64> = log_likelihoods
+        .iter()
+        .map(|log_likelihood| (log_likelihood - max).exp())
+        .collect();
+    let total: f64 = weights.iter().sum();
+    weights.iter().map(|weight| weight / total).collect()
+}
@@ -0,0 +1,146 @@
+use crate::neighbours::{PrimerMatch, PrimerNeighbours};
+use debruijn::Kmer;
+
+/// leading bases of a k-mer used to index directly into its bucket (4^10 buckets, 4 MB)
+const PREFIX_BASES: usize = 10;
+
+/// a primer set a k-mer belongs to and the primer it matches there
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PrimerHit<K> {
+    /// position of the primer set in the index
+    pub primer_set: u32,
+    pub primer: PrimerMatch<K>,
+}
+
+/// every primer k-mer (and mismatch neighbour) of all indexed primer sets, looked up once per
+/// read end whatever the number of primer sets. K-mers are bucketed directly on their leading
+/// bases and binary searched within the bucket, keeping memory proportional to the number of
+/// primer k-mers rather than 4^k.
+pub struct PrimerLookup<K> {
+    /// start of each bucket in keys, indexed by the leading PREFIX_BASES bases
+    buckets: Vec<u32>,
+    /// k-mer ranks, sorted
+    keys: Vec<u64>,
+    /// start of each key's hits in hits
+    hit_starts: Vec<u32>,
+    /// hits of each key, ordered by primer set
+    hits: Vec<PrimerHit<K>>,
+}
+
+/// collects the primer k-mers of each primer set before sorting them into a PrimerLookup
+pub struct PrimerLookupBuilder<K> {
+    entries: Vec<(u64, PrimerHit<K>)>,
+    num_primer_sets: u32,
+}
+
+impl<K: Kmer> Default for PrimerLookupBuilder<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Kmer> PrimerLookupBuilder<K> {
+    pub fn new() -> Self {
+        PrimerLookupBuilder {
+            entries: Vec::new(),
+            num_primer_sets: 0,
+        }
+    }
+
+    /// adds the exact primer k-mers and mismatch neighbours of the next primer set
+    pub fn add_primer_set(
+        &mut self,
+        primer_keys: impl Iterator<Item = K>,
+        neighbours: PrimerNeighbours<K>,
+    ) {
+        let primer_set = self.num_primer_sets;
+        self.entries.extend(primer_keys.map(|key| {
+            let primer = PrimerMatch::Primer(key, 0);
+            (key.to_u64(), PrimerHit { primer_set, primer })
+        }));
+        self.entries.extend(
+            neighbours
+                .into_iter()
+                .map(|(key, primer)| (key.to_u64(), PrimerHit { primer_set, primer })),
+        );
+        self.num_primer_sets += 1;
+    }
+
+    pub fn build(self) -> PrimerLookup<K> {
+        let mut entries = self.entries;
+        entries.sort_by_key(|&(rank, hit)| (rank, hit.primer_set));
+
+        let mut keys = Vec::new();
+        let mut hit_starts = Vec::new();
+        let mut hits = Vec::with_capacity(entries.len());
+        for (rank, hit) in entries {
+            if keys.last() != Some(&rank) {
+                keys.push(rank);
+                hit_starts.push(hits.len() as u32);
+            }
+            hits.push(hit);
+        }
+        hit_starts.push(hits.len() as u32);
+        PrimerLookup::from_parts(keys, hit_starts, hits)
+    }
+}
+
+impl<K: Kmer> PrimerLookup<K> {
+    /// a lookup of sorted k-mer ranks, the start of each k-mer's hits and the hits themselves
+    pub fn from_parts(keys: Vec<u64>, hit_starts: Vec<u32>, hits: Vec<PrimerHit<K>>) -> Self {
+        let mut buckets = vec![0; (1 << (2 * prefix_bases::<K>())) + 1];
+        for &rank in &keys {
+            buckets[bucket::<K>(rank) + 1] += 1;
+        }
+        for i in 1..buckets.len() {
+            buckets[i] += buckets[i - 1];
+        }
+        log::debug!(
+            "primer lookup of {} k-mers with {} primer set hits",
+            keys.len(),
+            hits.len()
+        );
+        PrimerLookup {
+            buckets,
+            keys,
+            hit_starts,
+            hits,
+        }
+    }
+
+    /// the sorted k-mer ranks, start of each k-mer's hits and hits, as taken by from_parts
+    pub fn parts(&self) -> (&[u64], &[u32], &[PrimerHit<K>]) {
+        (&self.keys, &self.hit_starts, &self.hits)
+    }
+
+    /// primer sets containing the k-mer, exactly or within their mismatch neighbourhood
+    pub fn hits(&self, key: K) -> &[PrimerHit<K>] {
+        let rank = key.to_u64();
+        let bucket = bucket::<K>(rank);
+        let start = self.buckets[bucket] as usize;
+        let end = self.buckets[bucket + 1] as usize;
+        match self.keys[start..end].binary_search(&rank) {
+            Ok(pos) => {
+                let i = start + pos;
+                &self.hits[self.hit_starts[i] as usize..self.hit_starts[i + 1] as usize]
+            }
+            Err(_) => &[],
+        }
+    }
+
+    /// the primer a k-mer matches in one primer set
+    pub fn primer_match(&self, key: K, primer_set: usize) -> Option<PrimerMatch<K>> {
+        self.hits(key)
+            .iter()
+            .find(|hit| hit.primer_set as usize == primer_set)
+            .map(|hit| hit.primer)
+    }
+}
+
+fn prefix_bases<K: Kmer>() -> usize {
+    PREFIX_BASES.min(K::k())
+}
+
+fn bucket<K: Kmer>(rank: u64) -> usize {
+    (rank >> (2 * (K::k() - prefix_bases::<K>()))) as usize
+}
@@ -4,51 +4,309 @@ use human_panic::setup_panic;
 #[cfg(debug_assertions)]
 extern crate better_panic;
 
+use ampseer::{
+    builtin::BUILTIN_SCHEMES,
+    index_file, kmer,
+    overlap::SchemeOverlap,
+    reads::Subsample,
+    report,
+    report::{BatchRow, OutputFormat},
+    samplesheet::{self, Sample},
+    stopping::StoppingRule,
+    watch::{CallUpdate, DirectoryWatch, WatchSettings},
+    CallCriteria, ClassificationResult, Classifier, PrimerSetIndex,
+};
 use anyhow::{anyhow, Context, Result};
-use clap::Parser;
-use debruijn::{dna_string::*, Mer};
-use debruijn::{kmer::Kmer16, Vmer};
+use clap::{Args, Parser, Subcommand};
+use debruijn::Kmer;
+use rayon::prelude::*;
 use simple_logger::SimpleLogger;
-use std::cmp::Ordering;
 use std::{
-    collections::hash_map::Entry, collections::HashMap, collections::HashSet, fs::File,
-    io::BufReader, io::Read, path::PathBuf,
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
 };
 
+/// bootstrap replicates settling calls for --confidence-target when --bootstraps is not given
+const SETTLING_BOOTSTRAPS: usize = 100;
+/// --confidence-target of --watch when none is given
+const DEFAULT_WATCH_CONFIDENCE_TARGET: f32 = 0.95;
+
 #[derive(Parser)]
 #[clap(author, version, about)]
+#[clap(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Cli {
-    /// File containing reads to examine (or /dev/stdin)
+    #[clap(subcommand)]
+    command: Option<Command>,
+
+    /// File containing reads to examine in FASTQ, SAM, BAM or CRAM format (or /dev/stdin),
+    /// optionally compressed with gzip, bgzip, zstd or bzip2
     #[clap(short, long, value_parser, value_name = "FILE")]
     reads: Option<PathBuf>,
 
-    /// Files containing primer sets to check against
+    /// File containing the second mates of paired-end --reads, in the same order. Interleaved
+    /// pairs in --reads are detected from the read names, or the mate flags of SAM, BAM and CRAM
+    /// records
+    #[clap(long, value_parser, value_name = "FILE", requires = "reads")]
+    mate: Option<PathBuf>,
+
+    /// Classify the reads files appearing in this directory (and each barcode subdirectory) as
+    /// the basecaller finishes them, printing updated calls until --watch-timeout
+    #[clap(long, value_parser, value_name = "DIR", conflicts_with_all = ["reads", "mate"])]
+    watch: Option<PathBuf>,
+
+    /// Stop reading once this fraction of bootstrap replicates reproduce the call, evaluated
+    /// every --check-every reads (default 0.95 with --watch, where each sample stops separately)
+    #[clap(long, value_name = "FRACTION", value_parser = parse_confidence_target)]
+    confidence_target: Option<f32>,
+
+    /// Reads between evaluations of the call for --confidence-target
+    #[clap(long, value_name = "N", default_value_t = 10_000,
+        value_parser = clap::value_parser!(u64).range(1..))]
+    check_every: u64,
+
+    /// Stop reading after this many reads (or read pairs) whatever the call
+    #[clap(long, value_name = "N", conflicts_with = "watch")]
+    max_reads: Option<u64>,
+
+    /// With --watch, seconds between checks for new reads files
+    #[clap(
+        long,
+        value_name = "SECONDS",
+        default_value_t = 10.0,
+        value_parser = parse_seconds,
+        allow_negative_numbers = true,
+        requires = "watch"
+    )]
+    poll_interval: f64,
+
+    /// With --watch, stop after this many seconds without new reads files, 0 to poll until
+    /// interrupted
+    #[clap(
+        long,
+        value_name = "SECONDS",
+        default_value_t = 0.0,
+        value_parser = parse_seconds,
+        allow_negative_numbers = true,
+        requires = "watch"
+    )]
+    watch_timeout: f64,
+
+    /// Files containing primer sets to check against (FASTA, or primer.bed with --reference)
     #[clap(
         short,
         long,
         value_parser,
         value_name = "FILE",
         num_args = 1..,
-        required = true
+        required_unless_present_any = ["index", "builtin"]
     )]
     primer_sets: Vec<PathBuf>,
 
+    /// Also check against the primer sets built into ampseer (see `ampseer list-schemes`)
+    #[clap(long)]
+    builtin: bool,
+
+    /// Primer index file written by `ampseer index`, used instead of --primer-sets. The index
+    /// fixes the k-mer size and maximum mismatches it was built with
+    #[clap(long, value_parser, value_name = "FILE",
+        conflicts_with_all = ["primer_sets", "reference", "builtin", "kmer_size", "max_mismatches"])]
+    index: Option<PathBuf>,
+
+    /// Reference FASTA used to extract primer sequences from primer.bed coordinates
+    #[clap(long, value_parser, value_name = "FILE")]
+    reference: Option<PathBuf>,
+
+    /// Number of bootstrap replicates (resampling reads, and dropping amplicons) used to
+    /// estimate how reproducible the call is, 0 to skip
+    #[clap(long, value_name = "N", default_value_t = 0)]
+    bootstraps: usize,
+
+    /// Estimate the proportion of reads from each primer set, for libraries mixing primer sets
+    #[clap(long)]
+    mixture: bool,
+
+    /// Classify a random subset of the reads (or read pairs): a fraction between 0 and 1, or a
+    /// number of reads drawn by reservoir sampling
+    #[clap(long, value_name = "FRACTION|COUNT", conflicts_with = "watch")]
+    subsample: Option<Subsample>,
+
+    /// Random seed for bootstrap replicates and --subsample
+    #[clap(long, default_value_t = 0)]
+    seed: u64,
+
+    /// Maximum mismatches between a read end and a primer k-mer, read ends equally close to
+    /// several primers are not counted
+    #[clap(long, value_name = "N", default_value_t = 0, value_parser = clap::value_parser!(u8).range(0..=2))]
+    max_mismatches: u8,
+
+    /// Search for primer k-mers ending within N bases of each read end (e.g. after adapters
+    /// and barcodes), 0 to examine only the k-mers at the very ends
+    #[clap(long, value_name = "N", default_value_t = 0)]
+    search_window: usize,
+
+    /// Length of the primer k-mers compared with read ends, at most the length of the shortest
+    /// primer
+    #[clap(long, value_name = "K", default_value_t = kmer::DEFAULT_KMER_SIZE,
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new()
+            .range(kmer::MIN_KMER_SIZE as u64..=kmer::MAX_KMER_SIZE as u64))]
+    kmer_size: usize,
+
+    /// Number of threads classifying reads
+    #[clap(short, long, value_name = "N", default_value_t = 1)]
+    threads: usize,
+
+    /// Minimum reads on either primer of an amplicon for it to count as observed
+    #[clap(long, value_name = "N", default_value_t = 1,
+        value_parser = clap::value_parser!(i64).range(1..))]
+    min_amplicon_hits: i64,
+
+    /// Minimum fraction of a primer set's amplicons that must be observed before it can be
+    /// called
+    #[clap(long, value_name = "FRACTION", default_value_t = ampseer::DEFAULT_MIN_AMPLICON_FRACTION,
+        value_parser = parse_fraction)]
+    min_amplicon_fraction: f32,
+
+    /// Minimum fraction of the amplicons observed by the most complete primer set that must be
+    /// observed before a primer set can be called (e.g. 0.5 to ignore a contaminating kit)
+    #[clap(long, value_name = "FRACTION",
+        default_value_t = ampseer::DEFAULT_MIN_RELATIVE_AMPLICON_FRACTION,
+        value_parser = parse_fraction)]
+    min_relative_amplicon_fraction: f32,
+
+    /// Write per-amplicon primer hits and dropouts of the detected primer set to this file
+    #[clap(long, value_parser, value_name = "FILE")]
+    amplicon_report: Option<PathBuf>,
+
+    /// Format of the result written to stdout
+    #[clap(long, value_enum, default_value_t = OutputFormat::Text)]
+    output_format: OutputFormat,
+
     /// Increase logging verbosity with -d or -dd
-    #[clap(short, long, action = clap::ArgAction::Count)]
+    #[clap(short, long, action = clap::ArgAction::Count, global = true)]
     debug: u8,
 }
 
-struct PrimerSet {
-    name: String,
-    primer_counter: HashMap<Kmer16, i64>,
-    num_consistent_reads: i64,
-    num_inconsistent_reads: i64,
-    frac_consistent: f32,
+#[derive(Subcommand)]
+enum Command {
+    /// Precompile primer sets into a single index file for --index
+    Index(IndexArgs),
+    /// List the primer sets built into ampseer
+    ListSchemes,
+    /// Show the primer k-mers shared between primer sets and the amplicons unique to each
+    CompareSchemes(PrimerSetArgs),
+    /// Classify every sample of a sample sheet, writing one row per sample
+    Batch(BatchArgs),
+}
+
+#[derive(Args)]
+struct PrimerSetArgs {
+    /// Files containing primer sets (FASTA, or primer.bed with --reference)
+    #[clap(short, long, value_parser, value_name = "FILE", num_args = 1..,
+        required_unless_present = "builtin")]
+    primer_sets: Vec<PathBuf>,
+
+    /// Also include the primer sets built into ampseer
+    #[clap(long)]
+    builtin: bool,
+
+    /// Reference FASTA used to extract primer sequences from primer.bed coordinates
+    #[clap(long, value_parser, value_name = "FILE")]
+    reference: Option<PathBuf>,
+
+    /// Length of the primer k-mers
+    #[clap(long, value_name = "K", default_value_t = kmer::DEFAULT_KMER_SIZE,
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new()
+            .range(kmer::MIN_KMER_SIZE as u64..=kmer::MAX_KMER_SIZE as u64))]
+    kmer_size: usize,
+}
+
+#[derive(Args)]
+struct IndexArgs {
+    #[clap(flatten)]
+    primer_sets: PrimerSetArgs,
+
+    /// Maximum mismatches between a read end and a primer k-mer
+    #[clap(long, value_name = "N", default_value_t = 0, value_parser = clap::value_parser!(u8).range(0..=2))]
+    max_mismatches: u8,
+
+    /// Index file to write
+    #[clap(short, long, value_parser, value_name = "FILE")]
+    output: PathBuf,
+}
+
+#[derive(Args)]
+struct BatchArgs {
+    /// Tab separated file of sample name, reads file and optional mate file per line
+    #[clap(long, value_parser, value_name = "FILE")]
+    samplesheet: PathBuf,
+
+    /// Files containing primer sets (FASTA, or primer.bed with --reference)
+    #[clap(short, long, value_parser, value_name = "FILE", num_args = 1..,
+        required_unless_present_any = ["index", "builtin"])]
+    primer_sets: Vec<PathBuf>,
+
+    /// Also include the primer sets built into ampseer
+    #[clap(long)]
+    builtin: bool,
+
+    /// Primer index file written by `ampseer index`, used instead of --primer-sets. The index
+    /// fixes the k-mer size and maximum mismatches it was built with
+    #[clap(long, value_parser, value_name = "FILE",
+        conflicts_with_all = ["primer_sets", "reference", "builtin", "kmer_size", "max_mismatches"])]
+    index: Option<PathBuf>,
+
+    /// Reference FASTA used to extract primer sequences from primer.bed coordinates
+    #[clap(long, value_parser, value_name = "FILE")]
+    reference: Option<PathBuf>,
+
+    /// Length of the primer k-mers
+    #[clap(long, value_name = "K", default_value_t = kmer::DEFAULT_KMER_SIZE,
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new()
+            .range(kmer::MIN_KMER_SIZE as u64..=kmer::MAX_KMER_SIZE as u64))]
+    kmer_size: usize,
+
+    /// Maximum mismatches between a read end and a primer k-mer
+    #[clap(long, value_name = "N", default_value_t = 0, value_parser = clap::value_parser!(u8).range(0..=2))]
+    max_mismatches: u8,
+
+    /// Search for primer k-mers ending within N bases of each read end
+    #[clap(long, value_name = "N", default_value_t = 0)]
+    search_window: usize,
+
+    /// Number of samples classified at once
+    #[clap(short, long, value_name = "N", default_value_t = 1)]
+    threads: usize,
+
+    /// Number of bootstrap replicates estimating how reproducible each sample's call is, 0 to
+    /// skip
+    #[clap(long, value_name = "N", default_value_t = 0)]
+    bootstraps: usize,
+
+    /// Random seed for bootstrap replicates
+    #[clap(long, default_value_t = 0)]
+    seed: u64,
+
+    /// Minimum reads on either primer of an amplicon for it to count as observed
+    #[clap(long, value_name = "N", default_value_t = 1,
+        value_parser = clap::value_parser!(i64).range(1..))]
+    min_amplicon_hits: i64,
+
+    /// Minimum fraction of a primer set's amplicons that must be observed before it can be
+    /// called
+    #[clap(long, value_name = "FRACTION", default_value_t = ampseer::DEFAULT_MIN_AMPLICON_FRACTION,
+        value_parser = parse_fraction)]
+    min_amplicon_fraction: f32,
+
+    /// Minimum fraction of the amplicons observed by the most complete primer set that must be
+    /// observed before a primer set can be called (e.g. 0.5 to ignore a contaminating kit)
+    #[clap(long, value_name = "FRACTION",
+        default_value_t = ampseer::DEFAULT_MIN_RELATIVE_AMPLICON_FRACTION,
+        value_parser = parse_fraction)]
+    min_relative_amplicon_fraction: f32,
 }
 
-const EXPECTED_NON_MATCHING_RATIO: f32 = 0.005;
-const DEFAULT_PRIMER_SET: &str = "unknown";
-const MER_SIZE: usize = 16;
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Human Panic. Only enabled when *not* debugging.
     #[cfg(not(debug_assertions))]
@@ -83,7 +341,309 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         .init()
         .unwrap();
 
-    check_inputs(&args)?;
+    match &args.command {
+        Some(Command::Index(index_args)) => {
+            kmer::dispatch_kmer_size!(index_args.primer_sets.kmer_size, write_index(index_args))?
+        }
+        Some(Command::CompareSchemes(compare_args)) => {
+            kmer::dispatch_kmer_size!(compare_args.kmer_size, compare_schemes(compare_args))?
+        }
+        Some(Command::ListSchemes) => list_schemes()?,
+        Some(Command::Batch(batch_args)) => {
+            let kmer_size = index_kmer_size(batch_args.index.as_deref(), batch_args.kmer_size)?;
+            kmer::dispatch_kmer_size!(kmer_size, batch(batch_args))?
+        }
+        None => {
+            check_inputs(&args)?;
+            let kmer_size = index_kmer_size(args.index.as_deref(), args.kmer_size)?;
+            kmer::dispatch_kmer_size!(kmer_size, run(&args))?
+        }
+    }
+
+    Ok(())
+}
+
+/// the k-mer size an index file was built with, or kmer_size without one
+fn index_kmer_size(index: Option<&Path>, kmer_size: usize) -> Result<usize> {
+    match index {
+        Some(index) => index_file::kmer_size(index),
+        None => Ok(kmer_size),
+    }
+}
+
+/// loads the index file if given, otherwise indexes the primer sets
+fn load_or_build_index<K: Kmer>(
+    index: Option<&Path>,
+    primer_sets: &[PathBuf],
+    builtin: bool,
+    reference: Option<&Path>,
+    max_mismatches: u8,
+) -> Result<PrimerSetIndex<K>> {
+    match index {
+        Some(index) => PrimerSetIndex::<K>::load(index),
+        None => build_index::<K>(primer_sets, builtin, reference, max_mismatches),
+    }
+}
+
+/// indexes the primer sets, extracting primer.bed sequences from the reference if given
+fn build_index<K: Kmer>(
+    primer_sets: &[PathBuf],
+    builtin: bool,
+    reference: Option<&Path>,
+    max_mismatches: u8,
+) -> Result<PrimerSetIndex<K>> {
+    let mut index_builder = PrimerSetIndex::<K>::builder().max_mismatches(max_mismatches);
+    if builtin {
+        index_builder = index_builder.builtin_primer_sets();
+    }
+    for primer_set in primer_sets {
+        index_builder = index_builder.primer_set_file(primer_set);
+    }
+    if let Some(reference) = reference {
+        index_builder = index_builder.reference(reference);
+    }
+    index_builder.build()
+}
+
+/// writes the primer sets to an index file for later runs with --index
+fn write_index<K: Kmer>(args: &IndexArgs) -> Result<()> {
+    let index = build_index::<K>(
+        &args.primer_sets.primer_sets,
+        args.primer_sets.builtin,
+        args.primer_sets.reference.as_deref(),
+        args.max_mismatches,
+    )?;
+    index.write(&args.output)
+}
+
+/// prints the primer k-mers shared between the primer sets, warning about identical sets
+fn compare_schemes<K: Kmer>(args: &PrimerSetArgs) -> Result<()> {
+    let index = build_index::<K>(
+        &args.primer_sets,
+        args.builtin,
+        args.reference.as_deref(),
+        0,
+    )?;
+    let overlap = SchemeOverlap::new(&index);
+    for contained in overlap.indistinguishable() {
+        if contained.identical {
+            log::warn!(
+                "{} and {} share all of their primer k-mers and cannot be told apart at k={}",
+                contained.primer_set,
+                contained.within,
+                overlap.kmer_size
+            );
+        } else {
+            log::warn!(
+                "all primer k-mers of {} are also in {}, so reads cannot positively identify {} at k={}",
+                contained.primer_set,
+                contained.within,
+                contained.primer_set,
+                overlap.kmer_size
+            );
+        }
+    }
+    overlap.write(&mut std::io::stdout().lock())
+}
+
+/// prints the name, version, amplicon count and source of each built in primer set
+fn list_schemes() -> Result<()> {
+    let mut out = std::io::stdout().lock();
+    writeln!(out, "name\tversion\tnum_amplicons\tsource")?;
+    for scheme in BUILTIN_SCHEMES {
+        writeln!(
+            out,
+            "{}\t{}\t{}\t{}",
+            scheme.name,
+            scheme.version,
+            scheme.num_amplicons()?,
+            scheme.source
+        )?;
+    }
+    Ok(())
+}
+
+/// classifies every sample of the sample sheet against primer sets indexed once, writing a
+/// table with a row per sample in sample sheet order
+fn batch<K: Kmer + Send + Sync>(args: &BatchArgs) -> Result<()> {
+    let samples = samplesheet::read_samplesheet(&args.samplesheet)?;
+    let index = load_or_build_index::<K>(
+        args.index.as_deref(),
+        &args.primer_sets,
+        args.builtin,
+        args.reference.as_deref(),
+        args.max_mismatches,
+    )?;
+    let criteria = CallCriteria {
+        min_amplicon_hits: args.min_amplicon_hits,
+        min_amplicon_fraction: args.min_amplicon_fraction,
+        min_relative_amplicon_fraction: args.min_relative_amplicon_fraction,
+    };
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(args.threads.max(1))
+        .build()?;
+    let results = pool.install(|| {
+        samples
+            .par_iter()
+            .map(|sample| {
+                let mut result = classify_sample(&index, sample, args.search_window, &criteria)
+                    .with_context(|| anyhow!("Failed to classify sample {}", sample.name))?;
+                result.bootstrap(args.bootstraps, args.seed);
+                Ok(result)
+            })
+            .collect::<Result<Vec<ClassificationResult<K>>>>()
+    })?;
+    let rows: Vec<BatchRow<K>> = samples
+        .iter()
+        .zip(&results)
+        .map(|(sample, result)| BatchRow {
+            sample: &sample.name,
+            num_reads: result.num_reads,
+            call: &result.call,
+            primer_sets: &result.primer_sets,
+        })
+        .collect();
+    report::write_batch_report(&mut std::io::stdout().lock(), &rows)
+}
+
+fn classify_sample<K: Kmer + Send + Sync>(
+    index: &PrimerSetIndex<K>,
+    sample: &Sample,
+    search_window: usize,
+    criteria: &CallCriteria,
+) -> Result<ClassificationResult<K>> {
+    log::info!("classifying sample {} from {:?}", sample.name, sample.reads);
+    let reads = File::open(&sample.reads)
+        .with_context(|| anyhow!("Could not find reads at {:?}", sample.reads))?;
+    let mates: Option<Box<dyn std::io::Read>> = match sample.mate.as_deref() {
+        Some(mate) => {
+            Some(Box::new(File::open(mate).with_context(|| {
+                anyhow!("Could not find mates at {:?}", mate)
+            })?))
+        }
+        None => None,
+    };
+    let mut classifier = Classifier::new(index).search_window(search_window);
+    classifier.add_reads_threaded(Box::new(reads), mates, 1)?;
+    Ok(classifier.finish(criteria))
+}
+
+/// classifies the reads files of the watched directory as they appear, printing a line whenever
+/// a sample's call changes, until no new files appear for the timeout. Polling goes on once every
+/// call is settled, as barcodes without reads yet may still appear
+fn watch<K: Kmer>(args: &Cli, dir: &Path, index: &PrimerSetIndex<K>) -> Result<()> {
+    let settings = WatchSettings {
+        criteria: CallCriteria {
+            min_amplicon_hits: args.min_amplicon_hits,
+            min_amplicon_fraction: args.min_amplicon_fraction,
+            min_relative_amplicon_fraction: args.min_relative_amplicon_fraction,
+        },
+        bootstraps: settling_bootstraps(args),
+        seed: args.seed,
+        confidence_target: args
+            .confidence_target
+            .unwrap_or(DEFAULT_WATCH_CONFIDENCE_TARGET),
+    };
+    let mut watched = DirectoryWatch::new(index, dir, args.search_window, settings);
+    let mut out = std::io::stdout().lock();
+    writeln!(
+        out,
+        "sample\tnum_files\tnum_reads\tcall\tconfidence\tbootstrap_read_agreement\tsettled"
+    )?;
+    let poll_interval = Duration::from_secs_f64(args.poll_interval);
+    let timeout = Duration::from_secs_f64(args.watch_timeout);
+    let mut last_new_files = Instant::now();
+    loop {
+        let polled = watched.poll()?;
+        if polled.num_files_read > 0 || polled.num_files_pending > 0 {
+            last_new_files = Instant::now();
+        }
+        for update in polled.updates {
+            write_call_update(&mut out, &update)?;
+        }
+        if !timeout.is_zero() && last_new_files.elapsed() >= timeout {
+            if watched.all_settled() {
+                log::info!(
+                    "no new reads files for {:?}, every call is settled",
+                    timeout
+                );
+            } else {
+                log::warn!(
+                    "no new reads files for {:?}, stopping with unsettled calls",
+                    timeout
+                );
+            }
+            break;
+        }
+        std::thread::sleep(poll_interval);
+    }
+    Ok(())
+}
+
+/// a number of seconds that can be slept for
+fn parse_fraction(value: &str) -> Result<f32, String> {
+    let fraction: f32 = value.parse().map_err(|e| format!("{}", e))?;
+    if (0.0..=1.0).contains(&fraction) {
+        Ok(fraction)
+    } else {
+        Err(format!("{} is not a fraction between 0 and 1", value))
+    }
+}
+
+fn parse_confidence_target(value: &str) -> Result<f32, String> {
+    let fraction: f32 = value.parse().map_err(|e| format!("{}", e))?;
+    if fraction > 0.0 && fraction <= 1.0 {
+        Ok(fraction)
+    } else {
+        Err(format!("{} is not a fraction above 0 and at most 1", value))
+    }
+}
+
+fn parse_seconds(value: &str) -> Result<f64, String> {
+    let seconds: f64 = value.parse().map_err(|e| format!("{}", e))?;
+    if seconds.is_finite() && seconds >= 0.0 {
+        Ok(seconds)
+    } else {
+        Err(format!("{} is not a number of seconds of 0 or more", value))
+    }
+}
+
+fn settling_bootstraps(args: &Cli) -> usize {
+    match args.bootstraps {
+        0 => SETTLING_BOOTSTRAPS,
+        bootstraps => bootstraps,
+    }
+}
+
+fn write_call_update(out: &mut impl Write, update: &CallUpdate) -> Result<()> {
+    writeln!(
+        out,
+        "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+        update.sample,
+        update.num_files,
+        update.num_reads,
+        update.primer_set,
+        update.confidence,
+        update.read_agreement,
+        update.settled
+    )?;
+    out.flush()?;
+    Ok(())
+}
+
+/// classifies the reads against the primer sets using k-mers of type K, writing the report
+fn run<K: Kmer + Send + Sync>(args: &Cli) -> Result<()> {
+    let index = load_or_build_index::<K>(
+        args.index.as_deref(),
+        &args.primer_sets,
+        args.builtin,
+        args.reference.as_deref(),
+        args.max_mismatches,
+    )?;
+    if let Some(dir) = args.watch.as_deref() {
+        return watch(args, dir, &index);
+    }
+
     // Handle reads on stdin or a file if provided
     let reads = if let Some(reads) = args.reads.as_deref() {
         Box::new(File::open(reads)?)
@@ -92,14 +652,54 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         Box::new(File::open("/dev/stdin")?)
     };
 
-    let mut primer_set_counters = import_primer_sets(&args.primer_sets)?;
+    let mates: Option<Box<dyn std::io::Read>> = match args.mate.as_deref() {
+        Some(mate) => Some(Box::new(File::open(mate)?)),
+        None => None,
+    };
+
+    let criteria = CallCriteria {
+        min_amplicon_hits: args.min_amplicon_hits,
+        min_amplicon_fraction: args.min_amplicon_fraction,
+        min_relative_amplicon_fraction: args.min_relative_amplicon_fraction,
+    };
+    let mut classifier = Classifier::new(&index).search_window(args.search_window);
+    if let Some(subsample) = args.subsample {
+        classifier = classifier.subsample(subsample, args.seed);
+    }
+    if args.confidence_target.is_some() || args.max_reads.is_some() {
+        classifier = classifier.stopping_rule(StoppingRule {
+            criteria,
+            confidence_target: args.confidence_target,
+            max_reads: args.max_reads,
+            check_every: args.check_every,
+            bootstraps: settling_bootstraps(args),
+            seed: args.seed,
+        });
+    }
+    classifier.add_reads_threaded(reads, mates, args.threads)?;
 
-    classify_reads(reads, &mut primer_set_counters)?;
+    let mut result = classifier.finish(&criteria);
+    if args.bootstraps > 0 {
+        result.bootstrap(args.bootstraps, args.seed);
+    }
+    if args.mixture {
+        result.mixture();
+    }
 
-    let ps_detected = identify_primer_set(&primer_set_counters);
+    if let Some(amplicon_report) = args.amplicon_report.as_deref() {
+        // report the called set, or the closest candidate when the call is unknown
+        if let Some(psc) = result.closest_primer_set() {
+            ampseer::amplicons::write_amplicon_report(amplicon_report, psc)?;
+        }
+    }
 
-    //TODO: add a way to output all primer sets and their confidence scores
-    println!("{:?}, {:?}", ps_detected.0, ps_detected.1);
+    report::write_report(
+        &mut std::io::stdout().lock(),
+        args.output_format,
+        &result.primer_sets,
+        &result.call,
+        &result.primer_offsets,
+    )?;
 
     Ok(())
 }
@@ -110,6 +710,10 @@ fn check_inputs(args: &Cli) -> Result<(), anyhow::Error> {
 
     if args.reads.clone().is_some_and(|reads| reads.exists())
         && args.primer_sets.iter().all(|ps| ps.exists())
+        && args.reference.iter().all(|reference| reference.exists())
+        && args.index.iter().all(|index| index.exists())
+        && args.mate.iter().all(|mate| mate.exists())
+        && args.watch.iter().all(|dir| dir.is_dir())
     {
         log::info!(
             "Searching for primers from {:?} in reads from: {:?}",
@@ -124,6 +728,34 @@ fn check_inputs(args: &Cli) -> Result<(), anyhow::Error> {
                 error_messages.push(format!("Could not find primer set at {:?}", ps.as_path()));
             }
         }
+        if args
+            .reference
+            .clone()
+            .is_some_and(|reference| !reference.exists())
+        {
+            error_messages.push(format!(
+                "Could not find reference at {:?}",
+                args.reference.as_ref().unwrap().as_path()
+            ));
+        }
+        if args.index.clone().is_some_and(|index| !index.exists()) {
+            error_messages.push(format!(
+                "Could not find index at {:?}",
+                args.index.as_ref().unwrap().as_path()
+            ));
+        }
+        if args.watch.clone().is_some_and(|dir| !dir.is_dir()) {
+            error_messages.push(format!(
+                "Could not find directory to watch at {:?}",
+                args.watch.as_ref().unwrap().as_path()
+            ));
+        }
+        if args.mate.clone().is_some_and(|mate| !mate.exists()) {
+            error_messages.push(format!(
+                "Could not find mates at {:?}",
+                args.mate.as_ref().unwrap().as_path()
+            ));
+        }
         if args.reads.clone().is_some_and(|reads| !reads.exists()) {
             error_messages.push(format!(
                 "Could not find reads at {:?}",
@@ -139,282 +771,3 @@ fn check_inputs(args: &Cli) -> Result<(), anyhow::Error> {
         Ok(())
     }
 }
-
-/// imports primer sets, creating primer_set_counter objects containing k-mers to search for
-fn import_primer_sets(primer_set_paths: &[PathBuf]) -> Result<Vec<PrimerSet>, anyhow::Error> {
-    let primer_set_counters: Vec<PrimerSet> = primer_set_paths
-        .iter()
-        .map(|ps_filename| {
-            let primer_reader = File::open(ps_filename.as_path())
-                .map(BufReader::new)
-                .map(noodles::fasta::Reader::new)
-                .with_context(|| {
-                    anyhow!(
-                        "Failed to open primer_sets file: {:?}",
-                        ps_filename.as_path()
-                    )
-                })
-                .unwrap();
-            //TODO: consider an array of size 65536 instead and just index into that array
-            let primer_counts: HashMap<Kmer16, i64> = primer_counts_from_file(primer_reader)
-                .with_context(|| anyhow!("Failed to read records"))
-                .unwrap();
-            PrimerSet {
-                name: ps_filename
-                    .file_stem()
-                    .unwrap()
-                    .to_string_lossy()
-                    .into_owned(),
-                primer_counter: primer_counts,
-                num_consistent_reads: 0,
-                num_inconsistent_reads: 0,
-                frac_consistent: 0.0,
-            }
-        })
-        .collect();
-
-    //TODO: compare all primer sets, removing ambiguous primers
-    Ok(primer_set_counters)
-}
-
-/// Adds an 8-bit (16 nt) representation of the primer to the counter hash.
-fn primer_counts_from_file(
-    mut primer_reader: noodles::fasta::Reader<BufReader<File>>,
-) -> Result<HashMap<Kmer16, i64>, anyhow::Error> {
-    let mut primer_counts: HashMap<Kmer16, i64> = HashMap::new();
-    for result in primer_reader.records() {
-        let record = result?;
-        let primer_seq = DnaString::from_acgt_bytes(record.sequence().as_ref());
-        let record_name = String::from_utf8_lossy(record.name()).to_lowercase();
-        let key: Kmer16 = if record_name.contains("left") {
-            assert!(MER_SIZE <= record.sequence().len());
-            primer_seq.slice(0, MER_SIZE).get_kmer(0)
-        } else if record_name.contains("right") {
-            let offset = record.sequence().len() - MER_SIZE;
-            assert!(offset > 0);
-            primer_seq
-                .slice(offset, record.sequence().len())
-                .get_kmer(0)
-        } else {
-            return Err(anyhow!(
-                "Unable to identify left/right primer from {:?}",
-                record.name()
-            ));
-        };
-
-        for key in [key, key.rc()] {
-            if primer_counts.insert(key, 0).is_some() {
-                log::info!("Ambiguous primer: {:?}", primer_seq);
-            }
-        }
-    }
-    Ok(primer_counts)
-}
-
-/// populates counts of primers observed in reads
-fn classify_reads(
-    reads: Box<dyn Read>,
-    primer_set_counters: &mut Vec<PrimerSet>,
-) -> Result<(), anyhow::Error> {
-    let mut fastq_reader = noodles::fastq::Reader::new(BufReader::new(reads));
-
-    // let mut fastq_reader = File::open(reads_file)
-    //     .map(BufReader::new)
-    //     .map(noodles::fastq::Reader::new)
-    //     .with_context(|| anyhow!("Failed to open reads file: {:?}", reads_file))?;
-
-    for result in fastq_reader.records() {
-        let record = result?;
-        let read_seq = DnaString::from_acgt_bytes(record.sequence());
-        if read_seq.len() < MER_SIZE {
-            log::warn!("skipping short read {:?}", read_seq);
-            break;
-        }
-        // extracts beginning and ending bases of each read
-        let left_key: Kmer16 = read_seq.slice(0, MER_SIZE).get_kmer(0);
-        let right_key: Kmer16 = read_seq
-            .slice(read_seq.len() - MER_SIZE, read_seq.len())
-            .get_kmer(0);
-
-        //primer_set_counters.par_iter_mut().for_each(|psc| {
-        for psc in &mut *primer_set_counters {
-            for key in [left_key, right_key] {
-                match psc.primer_counter.entry(key) {
-                    Entry::Occupied(val) => {
-                        *val.into_mut() += 1;
-                        psc.num_consistent_reads += 1;
-                    }
-                    Entry::Vacant(_) => psc.num_inconsistent_reads += 1,
-                }
-            }
-            psc.frac_consistent = psc.num_consistent_reads as f32
-                / (psc.num_consistent_reads + psc.num_inconsistent_reads) as f32
-        }
-    }
-    Ok(())
-}
-
-/// summarizes primer set observations deciding which primer set was used
-fn identify_primer_set(primer_set_counters: &[PrimerSet]) -> (String, f32) {
-    if primer_set_counters.is_empty() {
-        return (DEFAULT_PRIMER_SET.to_string(), 0.0);
-    };
-
-    //TODO: add requirement that X fraction of primers have been observed allowing for some dropouts
-    // a primer set with fewer reads classified but with all primers represented is more confident
-    // than one with more raw counts on fewer primers
-
-    //TODO: infer fragmentation or full length
-
-    //TODO: check that ratio of left and right are similar for each primer pair
-
-    let mut ps_fracs = primer_set_counters
-        .iter()
-        .map(|psc| {
-            log::debug!(
-                "{} consistent reads: {:?}",
-                psc.name,
-                psc.primer_counter
-                    .iter()
-                    .filter(|&(_, &count)| count > 0)
-                    .collect::<HashMap<&Kmer16, &i64>>()
-            );
-            log::info!(
-                "{} con/inconsistent reads: {}/{}",
-                psc.name,
-                psc.num_consistent_reads,
-                psc.num_inconsistent_reads
-            );
-            (String::from(&psc.name), psc.frac_consistent)
-        })
-        .collect::<Vec<(String, f32)>>();
-    //TODO add a bootstrapping confidence calculation - consider randomly selects 50% of amplicons 1000 times
-    //ideas: - consider random selections of reads  ( number of ways to select 80% of reads = permutations, data structure to hold 1000 seeds in columns )
-    // i need to figure out a way to increment the appropriate columns for each read (maybe a bitmask?)
-    // a function that returns a unique bitmask for each seed?
-
-    //       - consider random amplicons (simulating dropouts)
-    //       -
-    // score = num consistent with all amplicons answer/ 1000
-    ps_fracs.sort_unstable_by_key(|ps_frac| (ps_frac.1 * -1000.0) as i32);
-    log::debug!("matching fractions: {:?}", ps_fracs);
-    match ps_fracs.len().cmp(&1) {
-        Ordering::Equal => {
-            // can't use a ratio here - only one set is being checked
-            // typical ratio for non-matching library is 0.001
-            let confidence = ps_fracs[0].1 / EXPECTED_NON_MATCHING_RATIO;
-            if ps_fracs[0].1 >= EXPECTED_NON_MATCHING_RATIO {
-                (String::from(&ps_fracs[0].0), confidence)
-            } else {
-                (String::from(DEFAULT_PRIMER_SET), 0.0)
-            }
-        }
-        Ordering::Greater => {
-            let confidence = ps_fracs[0].1 / ps_fracs[1].1;
-            if ps_fracs[0].1 / ps_fracs[1].1 > EXPECTED_NON_MATCHING_RATIO * 1000.0 {
-                (String::from(&ps_fracs[0].0), confidence)
-            } else {
-                log::debug!("Resolving related primer sets");
-                let (primer_set, confidence) = compare_only_unique_primers(primer_set_counters);
-                (primer_set, confidence)
-            }
-        }
-        _ => (String::from(DEFAULT_PRIMER_SET), 0.0),
-    }
-}
-
-//number of signals about set identity = number of amplicons * 2
-//points of evidence are the total number of observed k-mers (sum of reads )
-//signal = number of points of evidence that are consistent with identified set
-//noise = number of points of evidence that are inconsistent with identified set
-//score = SNR
-
-//goal: score = 1 if all available evidence is in favor of winning candidate
-//      score = 0 if cannot differentiate between 2 candidates
-//winner - runnerup  =* confidence
-
-/// compares a ratio of only the unique keys to see if we can differentiate between two similar sets
-/// confidence is estimated considering the number of unique k-mers used
-fn compare_only_unique_primers(primer_set_counters: &[PrimerSet]) -> (String, f32) {
-    if primer_set_counters.len() < 2 {
-        (String::from(DEFAULT_PRIMER_SET), 0.0)
-    } else {
-        let mut top = &primer_set_counters[0];
-        let mut second = &primer_set_counters[0];
-        let mut max_consistent_reads = 0;
-        let mut second_consistent_reads = 0;
-        for psc in primer_set_counters {
-            if psc.num_consistent_reads > max_consistent_reads {
-                top = psc;
-                max_consistent_reads = top.num_consistent_reads;
-            }
-        }
-        for psc in primer_set_counters {
-            if psc.name == top.name {
-                continue;
-            }
-            if psc.num_consistent_reads > second_consistent_reads {
-                second = psc;
-                second_consistent_reads = psc.num_consistent_reads;
-            }
-        }
-
-        log::debug!(
-            "top primer_set {:?}({:?}), second primer_set: {:?}({:?})",
-            top.name,
-            top.num_consistent_reads,
-            second.name,
-            second.num_consistent_reads
-        );
-
-        let top_ps_keys: HashSet<Kmer16> = top.primer_counter.keys().cloned().collect();
-        let second_ps_keys: HashSet<Kmer16> = second.primer_counter.keys().cloned().collect();
-        let top_unique_keys = &top_ps_keys - &second_ps_keys;
-        let second_unique_keys = &second_ps_keys - &top_ps_keys;
-        let mut uniq_top_count = 0;
-        let mut uniq_second_count = 0;
-        for uniq_key in top_unique_keys {
-            let top_count = top.primer_counter.get(&uniq_key).unwrap_or(&0);
-            let second_count = second.primer_counter.get(&uniq_key).unwrap_or(&0);
-            uniq_top_count += top_count;
-            uniq_second_count += second_count;
-            log::debug!(
-                "top uniq_key: {:?} top/second {:?}/{:?}",
-                uniq_key,
-                top_count,
-                second_count
-            );
-        }
-        for uniq_key in second_unique_keys {
-            let top_count = top.primer_counter.get(&uniq_key).unwrap_or(&0);
-            let second_count = second.primer_counter.get(&uniq_key).unwrap_or(&0);
-            uniq_top_count += top_count;
-            uniq_second_count += second_count;
-            log::debug!(
-                "second uniq_key: {:?} top/second {:?}/{:?}",
-                uniq_key,
-                top_count,
-                second_count
-            );
-        }
-
-        let count_ratio = uniq_top_count as f32 / uniq_second_count as f32;
-        log::debug!(
-            "{}/{}= {}/{}",
-            top.name,
-            second.name,
-            uniq_top_count,
-            uniq_second_count
-        );
-        //TODO: figure out probability threshold to choose which one...
-        if count_ratio > EXPECTED_NON_MATCHING_RATIO * 100.0 {
-            //first set's uniq counts
-            (String::from(&top.name), 0.0)
-        } else if 1.0 / count_ratio > EXPECTED_NON_MATCHING_RATIO * 100.0 {
-            //second set
-            (String::from(&second.name), 0.0)
-        } else {
-            (String::from(DEFAULT_PRIMER_SET), 0.0)
-        }
-    }
-}
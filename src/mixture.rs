@@ -0,0 +1,248 @@
+use crate::{bootstrap::ReadEndTally, PrimerSet, EXPECTED_NON_MATCHING_RATIO};
+use debruijn::Kmer;
+use serde::Serialize;
+use std::{collections::HashMap, fmt};
+
+/// likelihood of a read end on a primer absent from a primer set, relative to one of its primers
+const MISSING_PRIMER_LIKELIHOOD: f64 = 1e-6;
+/// components need this many reads on primers found in no other candidate primer set
+const MIN_UNIQUE_READS: u64 = 10;
+/// standard deviations by which a component's unique reads must exceed those expected by
+/// chance, read ends of fragmented libraries land on the primers of unused primer sets too
+const MIN_EXCESS_Z_SCORE: f64 = 3.0;
+const MAX_ITERATIONS: usize = 1000;
+const CONVERGED_CHANGE: f64 = 1e-6;
+
+/// one primer set of a mixture
+#[derive(Debug, Serialize)]
+pub struct MixtureComponent {
+    pub primer_set: String,
+    /// estimated fraction of the reads on primers from this primer set
+    pub proportion: f32,
+    /// reads matching only this primer set, the evidence separating it from the others
+    pub unique_reads: u64,
+    /// unique reads expected by chance if the primer set was not used
+    pub expected_unique_reads: f32,
+}
+
+/// proportions of the reads attributable to each primer set, estimated by expectation
+/// maximization over the read ends matching each primer set
+#[derive(Debug, Serialize)]
+pub struct Mixture {
+    /// supported primer sets, largest proportion first
+    pub components: Vec<MixtureComponent>,
+    /// reads with a primer of any candidate primer set at either end
+    pub num_reads: u64,
+}
+
+impl fmt::Display for Mixture {
+    /// e.g. ARTIC_v4 87% + neb_vss2a 13%
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let components: Vec<String> = self
+            .components
+            .iter()
+            .map(|component| {
+                format!(
+                    "{} {:.0}%",
+                    component.primer_set,
+                    component.proportion * 100.0
+                )
+            })
+            .collect();
+        write!(f, "{}", components.join(" + "))
+    }
+}
+
+/// reads sharing the same primer set likelihoods
+struct ReadClass {
+    count: u64,
+    /// likelihood of the reads under each primer set
+    likelihoods: Vec<f64>,
+    /// primer sets with a primer at either read end
+    matching: Vec<bool>,
+}
+
+impl ReadClass {
+    /// the only primer set matching the reads, if just one does
+    fn unique_set(&self) -> Option<usize> {
+        let mut matching_sets = (0..self.matching.len()).filter(|&set| self.matching[set]);
+        match (matching_sets.next(), matching_sets.next()) {
+            (Some(set), None) => Some(set),
+            _ => None,
+        }
+    }
+}
+
+/// estimates the primer sets the reads came from, keeping primer sets with significantly more
+/// reads on their unique primers than expected by chance. None when no read end matches a
+/// primer.
+pub fn estimate_mixture<K: Kmer>(
+    primer_sets: &[PrimerSet<K>],
+    read_ends: &ReadEndTally<K>,
+) -> Option<Mixture> {
+    let read_classes = read_classes(primer_sets, read_ends);
+    let num_reads: u64 = read_classes.iter().map(|class| class.count).sum();
+    if num_reads == 0 {
+        return None;
+    }
+    let mut unique_reads = vec![0; primer_sets.len()];
+    for class in &read_classes {
+        if let Some(set) = class.unique_set() {
+            unique_reads[set] += class.count;
+        }
+    }
+    // each primer set matches about EXPECTED_NON_MATCHING_RATIO of the read ends by chance
+    let num_read_ends = 2 * read_ends.values().sum::<u64>();
+    let expected_unique_reads: Vec<f64> = unique_primer_fractions(primer_sets)
+        .iter()
+        .map(|fraction| EXPECTED_NON_MATCHING_RATIO * num_read_ends as f64 * fraction)
+        .collect();
+    let supported: Vec<bool> = (0..primer_sets.len())
+        .map(|set| {
+            let expected = expected_unique_reads[set].max(1.0);
+            let z_score = (unique_reads[set] as f64 - expected) / expected.sqrt();
+            unique_reads[set] >= MIN_UNIQUE_READS && z_score >= MIN_EXCESS_Z_SCORE
+        })
+        .collect();
+    if !supported.contains(&true) {
+        log::info!("no primer set has enough unique reads to estimate a mixture");
+        return Some(Mixture {
+            components: Vec::new(),
+            num_reads,
+        });
+    }
+    let proportions = expectation_maximization(&read_classes, &supported);
+
+    let mut components: Vec<MixtureComponent> = (0..primer_sets.len())
+        .filter(|&set| supported[set])
+        .map(|set| MixtureComponent {
+            primer_set: primer_sets[set].name.clone(),
+            proportion: proportions[set] as f32,
+            unique_reads: unique_reads[set],
+            expected_unique_reads: expected_unique_reads[set] as f32,
+        })
+        .collect();
+    components.sort_by(|a, b| b.proportion.total_cmp(&a.proportion));
+    let mixture = Mixture {
+        components,
+        num_reads,
+    };
+    log::info!("mixture of {} reads: {}", num_reads, mixture);
+    Some(mixture)
+}
+
+/// fraction of each primer set's primer k-mers found in no other primer set
+fn unique_primer_fractions<K: Kmer>(primer_sets: &[PrimerSet<K>]) -> Vec<f64> {
+    let mut num_sets: HashMap<K, usize> = HashMap::new();
+    for psc in primer_sets {
+        for key in psc.primer_counter.keys() {
+            *num_sets.entry(*key).or_insert(0) += 1;
+        }
+    }
+    primer_sets
+        .iter()
+        .map(|psc| {
+            let unique = psc
+                .primer_counter
+                .keys()
+                .filter(|key| num_sets[key] == 1)
+                .count();
+            unique as f64 / psc.primer_counter.len().max(1) as f64
+        })
+        .collect()
+}
+
+/// groups the reads by the primer sets matching their ends. A primer set explains a read end
+/// on one of its primers with likelihood 1 / (its number of primer k-mers).
+fn read_classes<K: Kmer>(
+    primer_sets: &[PrimerSet<K>],
+    read_ends: &ReadEndTally<K>,
+) -> Vec<ReadClass> {
+    let mut read_classes = Vec::new();
+    for (ends, &count) in read_ends {
+        let mut likelihoods = vec![1.0; primer_sets.len()];
+        let mut matching = vec![false; primer_sets.len()];
+        let mut any_match = false;
+        for end in [&ends.left, &ends.right] {
+            let end_matches: Vec<bool> = primer_sets
+                .iter()
+                .map(|psc| {
+                    end.get(psc.id)
+                        .copied()
+                        .flatten()
+                        .and_then(|key| psc.matched_primer(key))
+                        .is_some()
+                })
+                .collect();
+            if !end_matches.contains(&true) {
+                continue;
+            }
+            any_match = true;
+            for (set, psc) in primer_sets.iter().enumerate() {
+                if end_matches[set] {
+                    likelihoods[set] *= 1.0 / psc.primer_counter.len() as f64;
+                    matching[set] = true;
+                } else {
+                    likelihoods[set] *= MISSING_PRIMER_LIKELIHOOD;
+                }
+            }
+        }
+        if !any_match {
+            continue;
+        }
+        read_classes.push(ReadClass {
+            count,
+            likelihoods,
+            matching,
+        });
+    }
+    read_classes
+}
+
+/// proportion of the reads from each included primer set, starting from equal proportions.
+/// Reads matching none of the included primer sets are left out.
+fn expectation_maximization(read_classes: &[ReadClass], included: &[bool]) -> Vec<f64> {
+    let read_classes: Vec<&ReadClass> = read_classes
+        .iter()
+        .filter(|class| class.matching.iter().zip(included).any(|(&m, &i)| m && i))
+        .collect();
+    let num_included = included.iter().filter(|&&set| set).count() as f64;
+    let mut proportions: Vec<f64> = included
+        .iter()
+        .map(|&set| if set { 1.0 / num_included } else { 0.0 })
+        .collect();
+    for iteration in 0..MAX_ITERATIONS {
+        let mut expected_reads = vec![0.0; proportions.len()];
+        let mut total = 0.0;
+        for class in &read_classes {
+            let weights: Vec<f64> = proportions
+                .iter()
+                .zip(&class.likelihoods)
+                .map(|(proportion, likelihood)| proportion * likelihood)
+                .collect();
+            let class_total: f64 = weights.iter().sum();
+            if class_total == 0.0 {
+                continue;
+            }
+            for (expected, weight) in expected_reads.iter_mut().zip(weights) {
+                *expected += class.count as f64 * weight / class_total;
+            }
+            total += class.count as f64;
+        }
+        if total == 0.0 {
+            break;
+        }
+        let updated: Vec<f64> = expected_reads.iter().map(|reads| reads / total).collect();
+        let change = updated
+            .iter()
+            .zip(&proportions)
+            .map(|(new, old)| (new - old).abs())
+            .fold(0.0, f64::max);
+        proportions = updated;
+        if change < CONVERGED_CHANGE {
+            log::debug!("mixture converged after {} iterations", iteration + 1);
+            break;
+        }
+    }
+    proportions
+}
@@ -0,0 +1,103 @@
+use crate::PrimerAmplicons;
+use debruijn::{Kmer, MerImmut};
+use std::collections::{hash_map::Entry, HashMap};
+
+/// the primer k-mer a mismatched read end k-mer is closest to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PrimerMatch<K> {
+    /// primer k-mer and the number of mismatches to it
+    Primer(K, u8),
+    /// equally close to more than one primer k-mer, at this number of mismatches
+    Ambiguous(u8),
+}
+
+impl<K> PrimerMatch<K> {
+    fn distance(&self) -> u8 {
+        match *self {
+            PrimerMatch::Primer(_, distance) | PrimerMatch::Ambiguous(distance) => distance,
+        }
+    }
+}
+
+/// k-mers within the mismatch neighbourhood of the primers (exact primer k-mers excluded)
+pub type PrimerNeighbours<K> = HashMap<K, PrimerMatch<K>>;
+
+/// builds the Hamming neighbourhood of every primer k-mer, up to max_mismatches substitutions.
+/// Neighbours that are equally close to primers of different amplicons (or sides) are kept as
+/// ambiguous so that they are not counted for either primer. Alternative primers of one amplicon
+/// side do not make a neighbour ambiguous, it is attributed to the lesser primer k-mer.
+pub fn primer_neighbours<K: Kmer>(
+    primer_amplicons: &PrimerAmplicons<K>,
+    max_mismatches: u8,
+) -> PrimerNeighbours<K> {
+    let mut neighbours = PrimerNeighbours::new();
+    if max_mismatches == 0 {
+        return neighbours;
+    }
+    for &primer_key in primer_amplicons.keys() {
+        let mut substituted = Vec::new();
+        substitutions(primer_key, 0, max_mismatches, 0, &mut substituted);
+        for (neighbour, distance) in substituted {
+            // an exact primer k-mer is never reassigned to another primer
+            if primer_amplicons.contains_key(&neighbour) {
+                continue;
+            }
+            match neighbours.entry(neighbour) {
+                Entry::Vacant(entry) => {
+                    entry.insert(PrimerMatch::Primer(primer_key, distance));
+                }
+                Entry::Occupied(mut entry) => {
+                    let closest = entry.get().distance();
+                    if distance < closest {
+                        entry.insert(PrimerMatch::Primer(primer_key, distance));
+                    } else if distance == closest {
+                        let same_amplicon_side = |other: K| {
+                            primer_amplicons.get(&other) == primer_amplicons.get(&primer_key)
+                        };
+                        match *entry.get() {
+                            PrimerMatch::Primer(other, _) if same_amplicon_side(other) => {
+                                entry.insert(PrimerMatch::Primer(other.min(primer_key), distance));
+                            }
+                            _ => {
+                                entry.insert(PrimerMatch::Ambiguous(distance));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+    let num_ambiguous = neighbours
+        .values()
+        .filter(|neighbour| matches!(neighbour, PrimerMatch::Ambiguous(_)))
+        .count();
+    log::info!(
+        "{} primer k-mer neighbours within {} mismatches, {} ambiguous",
+        neighbours.len(),
+        max_mismatches,
+        num_ambiguous
+    );
+    neighbours
+}
+
+/// collects every k-mer with 1..=remaining substitutions at or after position start,
+/// each exactly once along with its distance from the original k-mer
+fn substitutions<K: Kmer>(
+    key: K,
+    start: usize,
+    remaining: u8,
+    distance: u8,
+    out: &mut Vec<(K, u8)>,
+) {
+    if remaining == 0 {
+        return;
+    }
+    for pos in start..K::k() {
+        let original = key.get(pos);
+        for base in (0..4).filter(|&base| base != original) {
+            let neighbour = key.set(pos, base);
+            out.push((neighbour, distance + 1));
+            substitutions(neighbour, pos + 1, remaining - 1, distance + 1, out);
+        }
+    }
+}
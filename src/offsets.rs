@@ -0,0 +1,65 @@
+use crate::PrimerSide;
+use debruijn::{dna_string::DnaString, Kmer, Vmer};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// number of reads with a primer k-mer found at each distance (in bases) from the read ends
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct PrimerOffsets {
+    pub left: BTreeMap<usize, u64>,
+    pub right: BTreeMap<usize, u64>,
+}
+
+impl PrimerOffsets {
+    pub fn record(&mut self, side: PrimerSide, offset: usize) {
+        let offsets = match side {
+            PrimerSide::Left => &mut self.left,
+            PrimerSide::Right => &mut self.right,
+        };
+        *offsets.entry(offset).or_insert(0) += 1;
+    }
+
+    /// adds the offsets recorded by another classifier
+    pub fn merge(&mut self, other: &PrimerOffsets) {
+        for (offsets, other_offsets) in [
+            (&mut self.left, &other.left),
+            (&mut self.right, &other.right),
+        ] {
+            for (&offset, &count) in other_offsets {
+                *offsets.entry(offset).or_insert(0) += count;
+            }
+        }
+    }
+
+    /// median distance of the primers from the read ends, None when no primer was found
+    pub fn median(&self, side: PrimerSide) -> Option<usize> {
+        let offsets = match side {
+            PrimerSide::Left => &self.left,
+            PrimerSide::Right => &self.right,
+        };
+        median_of_counts(offsets)
+    }
+}
+
+/// median of values tallied with the number of times each was seen, None when empty
+pub(crate) fn median_of_counts(counts: &BTreeMap<usize, u64>) -> Option<usize> {
+    let total: u64 = counts.values().sum();
+    let mut seen = 0;
+    counts.iter().find_map(|(&value, &count)| {
+        seen += count;
+        (seen * 2 > total).then_some(value)
+    })
+}
+
+/// k-mers lying entirely within search_window bases of the start (Left) or end (Right) of a
+/// read, nearest the read end first. Only the k-mer at the very end is returned for windows shorter
+/// than a k-mer.
+pub fn end_kmers<K: Kmer>(read_seq: &DnaString, search_window: usize, side: PrimerSide) -> Vec<K> {
+    let num_offsets = search_window.min(read_seq.len()).saturating_sub(K::k()) + 1;
+    (0..num_offsets)
+        .map(|offset| match side {
+            PrimerSide::Left => read_seq.get_kmer(offset),
+            PrimerSide::Right => read_seq.get_kmer(read_seq.len() - K::k() - offset),
+        })
+        .collect()
+}
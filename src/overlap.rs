@@ -0,0 +1,134 @@
+use crate::{amplicons::amplicon_sort_key, PrimerSet, PrimerSetIndex};
+use anyhow::Result;
+use debruijn::Kmer;
+use std::{
+    collections::{BTreeSet, HashSet},
+    io::Write,
+};
+
+/// primer k-mers shared between every pair of indexed primer sets, computed before any reads are
+/// classified to tell whether the primer sets can be told apart
+pub struct SchemeOverlap {
+    pub kmer_size: usize,
+    pub names: Vec<String>,
+    /// primer k-mers (either orientation) shared by primer sets i and j, the diagonal holding the
+    /// primer k-mers of each set
+    pub shared_kmers: Vec<Vec<usize>>,
+    /// amplicons of each set with a primer k-mer found in no other primer set, in amplicon order
+    pub unique_amplicons: Vec<Vec<String>>,
+}
+
+/// a primer set with no primer k-mers (and so no amplicons) missing from another primer set,
+/// which no reads can positively identify over the other
+#[derive(Debug, PartialEq, Eq)]
+pub struct ContainedPrimerSet<'a> {
+    pub primer_set: &'a str,
+    pub within: &'a str,
+    /// the other primer set has no primer k-mers missing from this one either
+    pub identical: bool,
+}
+
+impl SchemeOverlap {
+    pub fn new<K: Kmer>(index: &PrimerSetIndex<K>) -> Self {
+        let primer_kmers: Vec<HashSet<K>> = index
+            .primer_sets()
+            .iter()
+            .map(canonical_primer_kmers)
+            .collect();
+        let shared_kmers = primer_kmers
+            .iter()
+            .map(|kmers| {
+                primer_kmers
+                    .iter()
+                    .map(|other| kmers.intersection(other).count())
+                    .collect()
+            })
+            .collect();
+        let unique_amplicons = index
+            .primer_sets()
+            .iter()
+            .enumerate()
+            .map(|(i, psc)| {
+                let unique: BTreeSet<_> = psc
+                    .primer_amplicons
+                    .iter()
+                    .filter(|(key, _)| {
+                        let canonical = canonical(**key);
+                        primer_kmers
+                            .iter()
+                            .enumerate()
+                            .all(|(j, kmers)| j == i || !kmers.contains(&canonical))
+                    })
+                    .flat_map(|(_, amplicons)| {
+                        amplicons
+                            .iter()
+                            .map(|(amplicon, _)| amplicon_sort_key(amplicon))
+                    })
+                    .collect();
+                unique
+                    .into_iter()
+                    .map(|(_, _, amplicon)| amplicon)
+                    .collect()
+            })
+            .collect();
+        SchemeOverlap {
+            kmer_size: K::k(),
+            names: index.primer_set_names().map(String::from).collect(),
+            shared_kmers,
+            unique_amplicons,
+        }
+    }
+
+    /// primer sets whose primer k-mers are all shared with another primer set, either identical
+    /// sets or subsets such as a scheme whose revision only adds primers (e.g. ARTIC v4 and
+    /// v4.1), listing identical pairs once
+    pub fn indistinguishable(&self) -> Vec<ContainedPrimerSet<'_>> {
+        let mut contained = Vec::new();
+        for i in 0..self.names.len() {
+            for j in i + 1..self.names.len() {
+                let shared = self.shared_kmers[i][j];
+                let i_within_j = shared == self.shared_kmers[i][i];
+                let j_within_i = shared == self.shared_kmers[j][j];
+                let (primer_set, within) = match (i_within_j, j_within_i) {
+                    (true, _) => (i, j),
+                    (false, true) => (j, i),
+                    (false, false) => continue,
+                };
+                contained.push(ContainedPrimerSet {
+                    primer_set: &self.names[primer_set],
+                    within: &self.names[within],
+                    identical: i_within_j && j_within_i,
+                });
+            }
+        }
+        contained
+    }
+
+    /// writes the shared k-mer matrix followed by the unique amplicons of each primer set
+    pub fn write(&self, out: &mut impl Write) -> Result<()> {
+        writeln!(out, "shared primer k-mers (k={})", self.kmer_size)?;
+        writeln!(out, "primer_set\t{}", self.names.join("\t"))?;
+        for (name, row) in self.names.iter().zip(&self.shared_kmers) {
+            let counts: Vec<String> = row.iter().map(|count| count.to_string()).collect();
+            writeln!(out, "{}\t{}", name, counts.join("\t"))?;
+        }
+        writeln!(out)?;
+        writeln!(out, "primer_set\tnum_unique_amplicons\tunique_amplicons")?;
+        for (name, unique) in self.names.iter().zip(&self.unique_amplicons) {
+            writeln!(out, "{}\t{}\t{}", name, unique.len(), unique.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+/// the lesser of a k-mer and its reverse complement, so each primer is counted once
+fn canonical<K: Kmer>(key: K) -> K {
+    key.min(key.rc())
+}
+
+fn canonical_primer_kmers<K: Kmer>(psc: &PrimerSet<K>) -> HashSet<K> {
+    psc.primer_amplicons
+        .keys()
+        .map(|&key| canonical(key))
+        .collect()
+}
@@ -0,0 +1,503 @@
+use anyhow::{anyhow, Result};
+use noodles::{bam, cram, fasta, fastq, sam};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::{
+    collections::VecDeque,
+    io::{self, BufRead, BufReader, Cursor, Read},
+    str::FromStr,
+    vec,
+};
+
+/// number of leading bytes examined to identify the format of a reads file
+const SNIFF_LEN: usize = 4096;
+/// gzip magic, also used by BGZF since each BGZF block is a gzip member
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+const BZIP2_MAGIC: &[u8] = b"BZh";
+const BAM_MAGIC: &[u8] = b"BAM\x01";
+const CRAM_MAGIC: &[u8] = b"CRAM";
+const SAM_HEADER_TAGS: [&[u8]; 5] = [b"@HD\t", b"@SQ\t", b"@RG\t", b"@PG\t", b"@CO\t"];
+/// a SAM alignment line has 11 mandatory tab-separated fields
+const SAM_MANDATORY_FIELDS: usize = 11;
+
+/// compression formats recognized by their leading bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+    Bzip2,
+}
+
+/// read file formats recognized by their leading bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadFormat {
+    Fastq,
+    Sam,
+    Bam,
+    Cram,
+}
+
+/// the sequences of a single read or of both mates of a read pair, in the orientation they
+/// were sequenced
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Fragment {
+    Single(Vec<u8>),
+    Pair(Vec<u8>, Vec<u8>),
+}
+
+/// a random subset of the reads, each read (or read pair) kept with the given probability or
+/// a fixed number of them chosen uniformly
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Subsample {
+    Fraction(f64),
+    Count(usize),
+}
+
+impl FromStr for Subsample {
+    type Err = String;
+
+    /// a whole number of reads, or a fraction of the reads between 0 and 1
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if let Ok(count) = value.parse::<usize>() {
+            return match count {
+                0 => Err(String::from("subsample count must be at least 1")),
+                _ => Ok(Subsample::Count(count)),
+            };
+        }
+        match value.parse::<f64>() {
+            Ok(fraction) if fraction > 0.0 && fraction <= 1.0 => Ok(Subsample::Fraction(fraction)),
+            _ => Err(format!(
+                "{} is neither a read count nor a fraction between 0 and 1",
+                value
+            )),
+        }
+    }
+}
+
+/// the fragments kept by the subsample, a fraction is drawn as the fragments are read while a
+/// count is reservoir sampled from all of them
+pub fn subsample<'a>(
+    fragments: impl Iterator<Item = Result<Fragment>> + 'a,
+    subsample: Subsample,
+    seed: u64,
+) -> Result<Box<dyn Iterator<Item = Result<Fragment>> + 'a>> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    match subsample {
+        Subsample::Fraction(fraction) => {
+            Ok(Box::new(fragments.filter(move |fragment| {
+                fragment.is_err() || rng.gen_bool(fraction)
+            })))
+        }
+        Subsample::Count(count) => {
+            let mut reservoir = Vec::with_capacity(count);
+            let mut num_fragments = 0;
+            for fragment in fragments {
+                let fragment = fragment?;
+                if reservoir.len() < count {
+                    reservoir.push(fragment);
+                } else {
+                    let replaced = rng.gen_range(0..=num_fragments);
+                    if replaced < count {
+                        reservoir[replaced] = fragment;
+                    }
+                }
+                num_fragments += 1;
+            }
+            log::info!("subsampled {} of {} reads", reservoir.len(), num_fragments);
+            Ok(Box::new(reservoir.into_iter().map(Ok)))
+        }
+    }
+}
+
+/// a read name and sequence
+struct NamedRead {
+    name: Vec<u8>,
+    sequence: Vec<u8>,
+    /// SAM flags of SAM, BAM and CRAM records, None for FASTQ
+    flags: Option<sam::alignment::record::Flags>,
+}
+
+/// yields the sequence of each read in the orientation it was sequenced
+pub enum ReadsReader {
+    Fastq(fastq::Reader<Box<dyn BufRead>>, fastq::Record),
+    Sam(sam::io::Reader<Box<dyn BufRead>>, sam::Record),
+    Bam(bam::io::Reader<Box<dyn Read>>, bam::Record),
+    Cram(Box<CramRecords>),
+}
+
+/// records of a CRAM stream, decoded one data container at a time. Without a reference, only
+/// unmapped reads and reads stored with their bases (or an embedded reference) can be decoded.
+pub struct CramRecords {
+    reader: cram::io::Reader<Box<dyn Read>>,
+    header: sam::Header,
+    reference_sequences: fasta::Repository,
+    records: vec::IntoIter<cram::Record>,
+    /// the EOF container was read, there is nothing after it to read
+    at_end: bool,
+}
+
+impl CramRecords {
+    fn new(reads: Box<dyn Read>) -> Result<Self> {
+        let mut reader = cram::io::Reader::new(reads);
+        let header = reader.read_header()?;
+        Ok(CramRecords {
+            reader,
+            header,
+            reference_sequences: fasta::Repository::default(),
+            records: Vec::new().into_iter(),
+            at_end: false,
+        })
+    }
+
+    /// the next record, returning None at the end of the stream
+    fn read_record(&mut self) -> io::Result<Option<cram::Record>> {
+        loop {
+            if let Some(record) = self.records.next() {
+                return Ok(Some(record));
+            }
+            if self.at_end {
+                return Ok(None);
+            }
+            let Some(container) = self.reader.read_data_container()? else {
+                self.at_end = true;
+                return Ok(None);
+            };
+            let compression_header = container.compression_header();
+            let mut records = Vec::new();
+            for slice in container.slices() {
+                let mut slice_records = slice.records(compression_header)?;
+                slice
+                    .resolve_records(
+                        &self.reference_sequences,
+                        &self.header,
+                        compression_header,
+                        &mut slice_records,
+                    )
+                    .map_err(|e| {
+                        io::Error::new(
+                            e.kind(),
+                            format!(
+                                "{}, CRAM reads compressed against a reference must be \
+                                 converted with `samtools fastq --reference` first",
+                                e
+                            ),
+                        )
+                    })?;
+                records.append(&mut slice_records);
+            }
+            self.records = records.into_iter();
+        }
+    }
+}
+
+impl ReadsReader {
+    /// detects the compression and format of the reads stream and positions the reader at the
+    /// first record
+    pub fn new(reads: Box<dyn Read>) -> Result<Self> {
+        let (prefix, reads) = sniff(decompress(reads)?)?;
+        match detect_format(&prefix) {
+            ReadFormat::Fastq => Ok(ReadsReader::Fastq(
+                fastq::Reader::new(Box::new(BufReader::new(reads))),
+                fastq::Record::default(),
+            )),
+            ReadFormat::Sam => {
+                let mut reader = sam::io::Reader::new(Box::new(BufReader::new(reads)) as _);
+                reader.read_header()?;
+                Ok(ReadsReader::Sam(reader, sam::Record::default()))
+            }
+            ReadFormat::Bam => {
+                // BGZF blocks have already been inflated by decompress
+                let mut reader = bam::io::Reader::from(reads);
+                reader.read_header()?;
+                Ok(ReadsReader::Bam(reader, bam::Record::default()))
+            }
+            ReadFormat::Cram => Ok(ReadsReader::Cram(Box::new(CramRecords::new(reads)?))),
+        }
+    }
+
+    /// reads the next primary record, returning None at the end of the stream
+    fn read_next(&mut self) -> io::Result<Option<NamedRead>> {
+        match self {
+            ReadsReader::Fastq(reader, record) => match reader.read_record(record)? {
+                0 => Ok(None),
+                _ => Ok(Some(NamedRead {
+                    name: record.name().to_vec(),
+                    sequence: record.sequence().to_vec(),
+                    flags: None,
+                })),
+            },
+            ReadsReader::Sam(reader, record) => loop {
+                if reader.read_record(record)? == 0 {
+                    return Ok(None);
+                }
+                let flags = record.flags()?;
+                if flags.is_secondary() || flags.is_supplementary() {
+                    continue;
+                }
+                let sequence = record.sequence().as_ref().to_vec();
+                return Ok(Some(NamedRead {
+                    name: record
+                        .name()
+                        .map(|name| name.as_ref().to_vec())
+                        .unwrap_or_default(),
+                    sequence: original_orientation(sequence, flags.is_reverse_complemented()),
+                    flags: Some(flags),
+                }));
+            },
+            ReadsReader::Bam(reader, record) => loop {
+                if reader.read_record(record)? == 0 {
+                    return Ok(None);
+                }
+                let flags = record.flags();
+                if flags.is_secondary() || flags.is_supplementary() {
+                    continue;
+                }
+                let sequence = record.sequence().iter().collect();
+                return Ok(Some(NamedRead {
+                    name: record
+                        .name()
+                        .map(|name| name.as_ref().to_vec())
+                        .unwrap_or_default(),
+                    sequence: original_orientation(sequence, flags.is_reverse_complemented()),
+                    flags: Some(flags),
+                }));
+            },
+            ReadsReader::Cram(records) => loop {
+                let Some(record) = records.read_record()? else {
+                    return Ok(None);
+                };
+                let flags = record.flags();
+                if flags.is_secondary() || flags.is_supplementary() {
+                    continue;
+                }
+                let sequence = record.sequence().as_ref().to_vec();
+                return Ok(Some(NamedRead {
+                    name: record
+                        .name()
+                        .map(|name| name.as_ref().to_vec())
+                        .unwrap_or_default(),
+                    sequence: original_orientation(sequence, flags.is_reverse_complemented()),
+                    flags: Some(flags),
+                }));
+            },
+        }
+    }
+}
+
+impl Iterator for ReadsReader {
+    type Item = Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_next()
+            .map(|read| read.map(|read| read.sequence))
+            .map_err(anyhow::Error::from)
+            .transpose()
+    }
+}
+
+/// yields single reads, or read pairs from separate mate files or interleaved records
+pub struct FragmentReader {
+    reads: ReadsReader,
+    mates: Option<ReadsReader>,
+    /// records read ahead to detect interleaved pairs
+    read_ahead: VecDeque<NamedRead>,
+    interleaved: bool,
+}
+
+impl FragmentReader {
+    /// pairs the records of reads and mates in order, or without mates detects interleaved
+    /// pairs from the names (and SAM flags) of the first two records
+    pub fn new(reads: Box<dyn Read>, mates: Option<Box<dyn Read>>) -> Result<Self> {
+        let mut reader = FragmentReader {
+            reads: ReadsReader::new(reads)?,
+            mates: mates.map(ReadsReader::new).transpose()?,
+            read_ahead: VecDeque::new(),
+            interleaved: false,
+        };
+        if reader.mates.is_none() {
+            for _ in 0..2 {
+                if let Some(read) = reader.reads.read_next()? {
+                    reader.read_ahead.push_back(read);
+                }
+            }
+            reader.interleaved = match (reader.read_ahead.front(), reader.read_ahead.get(1)) {
+                (Some(first), Some(second)) => is_mate_pair(first, second),
+                _ => false,
+            };
+            if reader.interleaved {
+                log::info!("reads are interleaved pairs");
+            }
+        }
+        Ok(reader)
+    }
+
+    /// true when reads are paired, from mate files or interleaved records
+    pub fn is_paired(&self) -> bool {
+        self.mates.is_some() || self.interleaved
+    }
+
+    fn next_read(&mut self) -> io::Result<Option<NamedRead>> {
+        match self.read_ahead.pop_front() {
+            Some(read) => Ok(Some(read)),
+            None => self.reads.read_next(),
+        }
+    }
+
+    fn read_fragment(&mut self) -> Result<Option<Fragment>> {
+        let Some(read) = self.next_read()? else {
+            if let Some(mate) = self
+                .mates
+                .as_mut()
+                .map(|mates| mates.read_next())
+                .transpose()?
+            {
+                if mate.is_some() {
+                    return Err(anyhow!("mate file has more reads than the reads file"));
+                }
+            }
+            return Ok(None);
+        };
+        let mate = if let Some(mates) = self.mates.as_mut() {
+            mates
+                .read_next()?
+                .ok_or_else(|| anyhow!("reads file has more reads than the mate file"))?
+        } else if self.interleaved {
+            self.next_read()?.ok_or_else(|| {
+                anyhow!(
+                    "interleaved read {} has no mate",
+                    String::from_utf8_lossy(&read.name)
+                )
+            })?
+        } else {
+            return Ok(Some(Fragment::Single(read.sequence)));
+        };
+        if !is_mate_pair(&read, &mate) {
+            return Err(anyhow!(
+                "read {} is paired with {}, mates must be in the same order",
+                String::from_utf8_lossy(&read.name),
+                String::from_utf8_lossy(&mate.name)
+            ));
+        }
+        Ok(Some(Fragment::Pair(read.sequence, mate.sequence)))
+    }
+}
+
+impl Iterator for FragmentReader {
+    type Item = Result<Fragment>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_fragment().transpose()
+    }
+}
+
+/// mates share a name once any /1 or /2 suffix is removed (Casava 1.8 names put the mate
+/// number after the whitespace, which noodles leaves out of the name). Two SAM, BAM or CRAM
+/// records must also be flagged as the two mates of a pair.
+fn is_mate_pair(read: &NamedRead, mate: &NamedRead) -> bool {
+    let flagged_as_mates = match (read.flags, mate.flags) {
+        (Some(first), Some(second)) => {
+            (first.is_first_segment() && second.is_last_segment())
+                || (first.is_last_segment() && second.is_first_segment())
+        }
+        _ => true,
+    };
+    !read.name.is_empty() && mate_stem(&read.name) == mate_stem(&mate.name) && flagged_as_mates
+}
+
+fn mate_stem(name: &[u8]) -> &[u8] {
+    match name {
+        [stem @ .., b'/', b'1' | b'2'] => stem,
+        _ => name,
+    }
+}
+
+/// wraps the stream in a decoder matching its compression (if any)
+fn decompress(reads: Box<dyn Read>) -> io::Result<Box<dyn Read>> {
+    let (prefix, reads) = sniff(reads)?;
+    let compression = detect_compression(&prefix);
+    log::debug!("reads compression: {:?}", compression);
+    Ok(match compression {
+        // multi-member decoding also handles bgzip and concatenated gzip files
+        Compression::Gzip => Box::new(flate2::read::MultiGzDecoder::new(reads)),
+        Compression::Zstd => Box::new(zstd::Decoder::new(reads)?),
+        Compression::Bzip2 => Box::new(bzip2::read::MultiBzDecoder::new(reads)),
+        Compression::None => reads,
+    })
+}
+
+/// identifies the compression of the stream from its leading bytes
+pub fn detect_compression(prefix: &[u8]) -> Compression {
+    if prefix.starts_with(GZIP_MAGIC) {
+        Compression::Gzip
+    } else if prefix.starts_with(ZSTD_MAGIC) {
+        Compression::Zstd
+    } else if prefix.starts_with(BZIP2_MAGIC) {
+        Compression::Bzip2
+    } else {
+        Compression::None
+    }
+}
+
+/// identifies the reads format from the leading (decompressed) bytes of the stream
+pub fn detect_format(prefix: &[u8]) -> ReadFormat {
+    if prefix.starts_with(BAM_MAGIC) {
+        ReadFormat::Bam
+    } else if prefix.starts_with(CRAM_MAGIC) {
+        ReadFormat::Cram
+    } else if SAM_HEADER_TAGS.iter().any(|tag| prefix.starts_with(tag))
+        || (!prefix.starts_with(b"@") && is_sam_alignment_line(prefix))
+    {
+        ReadFormat::Sam
+    } else {
+        ReadFormat::Fastq
+    }
+}
+
+/// headerless SAM starts directly with an alignment line
+fn is_sam_alignment_line(prefix: &[u8]) -> bool {
+    let first_line = prefix.split(|&b| b == b'\n').next().unwrap_or_default();
+    first_line.split(|&b| b == b'\t').count() >= SAM_MANDATORY_FIELDS
+}
+
+/// reads up to SNIFF_LEN bytes, returning them along with a reader that replays them
+fn sniff(mut reads: Box<dyn Read>) -> io::Result<(Vec<u8>, Box<dyn Read>)> {
+    let mut prefix = Vec::with_capacity(SNIFF_LEN);
+    (&mut reads)
+        .take(SNIFF_LEN as u64)
+        .read_to_end(&mut prefix)?;
+    let replay: Box<dyn Read> = Box::new(Cursor::new(prefix.clone()).chain(reads));
+    Ok((prefix, replay))
+}
+
+/// aligners store reverse strand reads reverse complemented, undo that so read ends are
+/// the sequenced ends
+fn original_orientation(sequence: Vec<u8>, is_reverse_complemented: bool) -> Vec<u8> {
+    if is_reverse_complemented {
+        reverse_complement(&sequence)
+    } else {
+        sequence
+    }
+}
+
+pub fn reverse_complement(sequence: &[u8]) -> Vec<u8> {
+    sequence
+        .iter()
+        .rev()
+        .map(|&base| complement(base))
+        .collect()
+}
+
+fn complement(base: u8) -> u8 {
+    match base {
+        b'A' => b'T',
+        b'C' => b'G',
+        b'G' => b'C',
+        b'T' => b'A',
+        b'a' => b't',
+        b'c' => b'g',
+        b'g' => b'c',
+        b't' => b'a',
+        _ => b'N',
+    }
+}
@@ -0,0 +1,261 @@
+use crate::{likelihood::HypothesisScore, offsets::PrimerOffsets, PrimerSet, PrimerSetCall};
+use anyhow::Result;
+use debruijn::Kmer;
+use serde::Serialize;
+use std::io::Write;
+
+/// how the classification result is written to stdout
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// the called primer set and its confidence
+    Text,
+    /// the call along with scores for every candidate primer set
+    Json,
+    /// one row per candidate primer set, repeating the call on every row
+    Tsv,
+}
+
+const TSV_HEADER: [&str; 24] = [
+    "primer_set",
+    "num_consistent_reads",
+    "num_inconsistent_reads",
+    "num_mismatched_reads",
+    "num_ambiguous_reads",
+    "frac_consistent",
+    "frac_amplicons_observed",
+    "unique_primer_reads",
+    "call",
+    "confidence",
+    "unknown_reason",
+    "bootstrap_read_agreement",
+    "bootstrap_amplicon_agreement",
+    "library_type",
+    "frac_reads_on_primers",
+    "frac_both_ends_same_amplicon",
+    "frac_left_primer_hits",
+    "one_sided_primer_hits",
+    "mixture",
+    "num_concordant_pairs",
+    "reads_consumed",
+    "stop_reason",
+    "log_likelihood",
+    "posterior",
+];
+
+/// columns of the batch table before the per primer set columns
+const BATCH_HEADER: [&str; 8] = [
+    "sample",
+    "num_reads",
+    "call",
+    "confidence",
+    "bootstrap_read_agreement",
+    "bootstrap_amplicon_agreement",
+    "unknown_reason",
+    "library_type",
+];
+/// columns repeated for each primer set in the batch table, prefixed with its name
+const BATCH_PRIMER_SET_COLUMNS: [&str; 11] = [
+    "num_consistent_reads",
+    "num_inconsistent_reads",
+    "num_mismatched_reads",
+    "num_ambiguous_reads",
+    "frac_consistent",
+    "frac_amplicons_observed",
+    "unique_primer_reads",
+    "frac_left_primer_hits",
+    "num_concordant_pairs",
+    "log_likelihood",
+    "posterior",
+];
+
+#[derive(Serialize)]
+#[serde(bound = "")]
+struct Report<'a, K: Kmer> {
+    call: &'a PrimerSetCall,
+    primer_sets: &'a [PrimerSet<K>],
+    primer_offsets: &'a PrimerOffsets,
+}
+
+/// writes the call (and for json/tsv the per primer set scores) in the requested format,
+/// json also includes the distribution of primer offsets from the read ends
+pub fn write_report<K: Kmer>(
+    out: &mut impl Write,
+    format: OutputFormat,
+    primer_set_counters: &[PrimerSet<K>],
+    call: &PrimerSetCall,
+    primer_offsets: &PrimerOffsets,
+) -> Result<()> {
+    match format {
+        OutputFormat::Text => {
+            match &call.bootstrap {
+                Some(bootstrap) => writeln!(
+                    out,
+                    "{:?}, {:?}, bootstrap agreement reads: {:?}, amplicons: {:?}",
+                    call.primer_set,
+                    call.confidence,
+                    bootstrap.read_agreement,
+                    bootstrap.amplicon_agreement
+                )?,
+                None => writeln!(out, "{:?}, {:?}", call.primer_set, call.confidence)?,
+            }
+            if let Some(mixture) = &call.mixture {
+                writeln!(out, "mixture: {}", mixture)?;
+            }
+            if let Some(consumed) = &call.reads_consumed {
+                writeln!(
+                    out,
+                    "reads consumed: {} ({})",
+                    consumed.num_reads, consumed.stop_reason
+                )?;
+            }
+        }
+        OutputFormat::Json => {
+            let report = Report {
+                call,
+                primer_sets: primer_set_counters,
+                primer_offsets,
+            };
+            serde_json::to_writer_pretty(&mut *out, &report)?;
+            writeln!(out)?;
+        }
+        OutputFormat::Tsv => {
+            writeln!(out, "{}", TSV_HEADER.join("\t"))?;
+            let layout = call.library_layout.as_ref();
+            for psc in primer_set_counters {
+                writeln!(
+                    out,
+                    "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                    psc.name,
+                    psc.num_consistent_reads,
+                    psc.num_inconsistent_reads,
+                    psc.num_mismatched_reads,
+                    psc.num_ambiguous_reads,
+                    psc.frac_consistent,
+                    psc.frac_amplicons_observed,
+                    unique_primer_reads(call, &psc.name)
+                        .map_or(String::new(), |count| count.to_string()),
+                    call.primer_set,
+                    call.confidence,
+                    call.unknown_reason.as_deref().unwrap_or(""),
+                    call.bootstrap
+                        .as_ref()
+                        .map_or(String::new(), |b| b.read_agreement.to_string()),
+                    call.bootstrap
+                        .as_ref()
+                        .map_or(String::new(), |b| b.amplicon_agreement.to_string()),
+                    layout.map_or(String::new(), |l| l.library_type.to_string()),
+                    layout.map_or(String::new(), |l| l.frac_reads_on_primers.to_string()),
+                    layout.map_or(String::new(), |l| l
+                        .frac_both_ends_same_amplicon
+                        .to_string()),
+                    psc.frac_left_primer_hits,
+                    psc.one_sided_primer_hits,
+                    call.mixture
+                        .as_ref()
+                        .map_or(String::new(), |mixture| mixture.to_string()),
+                    psc.num_concordant_pairs,
+                    call.reads_consumed
+                        .map_or(String::new(), |consumed| consumed.num_reads.to_string()),
+                    call.reads_consumed
+                        .map_or(String::new(), |consumed| consumed.stop_reason.to_string()),
+                    hypothesis_score(call, &psc.name)
+                        .map_or(String::new(), |score| score.log_likelihood.to_string()),
+                    hypothesis_score(call, &psc.name)
+                        .map_or(String::new(), |score| score.posterior.to_string()),
+                )?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// the classification of one sample of a batch
+pub struct BatchRow<'a, K: Kmer> {
+    pub sample: &'a str,
+    pub num_reads: u64,
+    pub call: &'a PrimerSetCall,
+    pub primer_sets: &'a [PrimerSet<K>],
+}
+
+/// writes one row per sample with the call and the scores of every primer set, each sample
+/// having been classified against the same primer sets
+pub fn write_batch_report<K: Kmer>(out: &mut impl Write, rows: &[BatchRow<K>]) -> Result<()> {
+    let mut header: Vec<String> = BATCH_HEADER
+        .iter()
+        .map(|column| column.to_string())
+        .collect();
+    if let Some(first) = rows.first() {
+        for psc in first.primer_sets {
+            for column in BATCH_PRIMER_SET_COLUMNS {
+                header.push(format!("{}_{}", psc.name, column));
+            }
+        }
+    }
+    writeln!(out, "{}", header.join("\t"))?;
+    for row in rows {
+        let mut fields = vec![
+            row.sample.to_string(),
+            row.num_reads.to_string(),
+            row.call.primer_set.clone(),
+            row.call.confidence.to_string(),
+            row.call
+                .bootstrap
+                .as_ref()
+                .map_or(String::new(), |b| b.read_agreement.to_string()),
+            row.call
+                .bootstrap
+                .as_ref()
+                .map_or(String::new(), |b| b.amplicon_agreement.to_string()),
+            row.call.unknown_reason.clone().unwrap_or_default(),
+            row.call
+                .library_layout
+                .as_ref()
+                .map_or(String::new(), |l| l.library_type.to_string()),
+        ];
+        for psc in row.primer_sets {
+            fields.extend([
+                psc.num_consistent_reads.to_string(),
+                psc.num_inconsistent_reads.to_string(),
+                psc.num_mismatched_reads.to_string(),
+                psc.num_ambiguous_reads.to_string(),
+                psc.frac_consistent.to_string(),
+                psc.frac_amplicons_observed.to_string(),
+                unique_primer_reads(row.call, &psc.name)
+                    .map_or(String::new(), |count| count.to_string()),
+                psc.frac_left_primer_hits.to_string(),
+                psc.num_concordant_pairs.to_string(),
+                hypothesis_score(row.call, &psc.name)
+                    .map_or(String::new(), |score| score.log_likelihood.to_string()),
+                hypothesis_score(row.call, &psc.name)
+                    .map_or(String::new(), |score| score.posterior.to_string()),
+            ]);
+        }
+        writeln!(out, "{}", fields.join("\t"))?;
+    }
+    Ok(())
+}
+
+/// the likelihood score of this set, if it was a candidate for the call
+fn hypothesis_score<'a>(call: &'a PrimerSetCall, primer_set: &str) -> Option<&'a HypothesisScore> {
+    call.likelihoods.as_ref().and_then(|likelihoods| {
+        likelihoods
+            .primer_sets
+            .iter()
+            .find(|score| score.primer_set == primer_set)
+    })
+}
+
+/// reads on primers unique to this set, if it was one of the two compared sets
+fn unique_primer_reads(call: &PrimerSetCall, primer_set: &str) -> Option<i64> {
+    call.unique_primer_comparison
+        .as_ref()
+        .and_then(|comparison| {
+            if comparison.top_primer_set == primer_set {
+                Some(comparison.top_unique_reads)
+            } else if comparison.second_primer_set == primer_set {
+                Some(comparison.second_unique_reads)
+            } else {
+                None
+            }
+        })
+}
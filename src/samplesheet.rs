@@ -0,0 +1,64 @@
+use anyhow::{anyhow, Context, Result};
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// a sample of a batch and its reads
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sample {
+    pub name: String,
+    pub reads: PathBuf,
+    /// second mates of paired-end reads
+    pub mate: Option<PathBuf>,
+}
+
+/// reads a tab separated sample sheet of sample name, reads file and optional mate file per line.
+/// Relative paths are relative to the sample sheet, a header line starting with "sample" and
+/// lines starting with # are skipped.
+pub fn read_samplesheet(path: &Path) -> Result<Vec<Sample>> {
+    let contents = fs::read_to_string(path)
+        .with_context(|| anyhow!("Failed to read sample sheet {:?}", path))?;
+    let base = path.parent().unwrap_or(Path::new(""));
+    let mut samples = Vec::new();
+    let mut names = HashSet::new();
+    for (line_number, line) in contents.lines().enumerate() {
+        let fields: Vec<&str> = line.split('\t').map(str::trim).collect();
+        if line.trim().is_empty()
+            || line.starts_with('#')
+            || (line_number == 0 && fields[0].eq_ignore_ascii_case("sample"))
+        {
+            continue;
+        }
+        let (name, reads, mate) = match fields[..] {
+            [name, reads] => (name, reads, None),
+            [name, reads, ""] => (name, reads, None),
+            [name, reads, mate] => (name, reads, Some(mate)),
+            _ => {
+                return Err(anyhow!(
+                    "line {} of sample sheet {:?} should have a sample name, reads file and \
+                     optional mate file separated by tabs",
+                    line_number + 1,
+                    path
+                ))
+            }
+        };
+        if !names.insert(name.to_string()) {
+            return Err(anyhow!(
+                "sample {} is listed more than once in {:?}",
+                name,
+                path
+            ));
+        }
+        samples.push(Sample {
+            name: name.to_string(),
+            reads: base.join(reads),
+            mate: mate.map(|mate| base.join(mate)),
+        });
+    }
+    if samples.is_empty() {
+        return Err(anyhow!("sample sheet {:?} lists no samples", path));
+    }
+    Ok(samples)
+}
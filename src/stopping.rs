@@ -0,0 +1,46 @@
+use crate::CallCriteria;
+use serde::Serialize;
+use std::fmt;
+
+/// when to stop reading before the end of the reads
+#[derive(Clone, Copy, Debug)]
+pub struct StoppingRule {
+    pub criteria: CallCriteria,
+    /// fraction of bootstrap replicates reproducing the call at which reading stops
+    pub confidence_target: Option<f32>,
+    /// reads examined before stopping regardless of the call
+    pub max_reads: Option<u64>,
+    /// reads between evaluations of the call
+    pub check_every: u64,
+    pub bootstraps: usize,
+    pub seed: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StopReason {
+    /// the call reached the confidence target
+    Settled,
+    MaxReads,
+    EndOfReads,
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let reason = match self {
+            StopReason::Settled => "settled",
+            StopReason::MaxReads => "max_reads",
+            StopReason::EndOfReads => "end_of_reads",
+        };
+        write!(f, "{}", reason)
+    }
+}
+
+/// how many reads were examined under a stopping rule and why reading stopped
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct ReadsConsumed {
+    pub num_reads: u64,
+    pub stop_reason: StopReason,
+    /// fraction of bootstrap replicates reproducing the call at the last evaluation
+    pub read_agreement: Option<f32>,
+}
@@ -0,0 +1,269 @@
+use crate::{CallCriteria, Classifier, PrimerSetIndex};
+use anyhow::{anyhow, Context, Result};
+use debruijn::Kmer;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{self, File},
+    path::{Path, PathBuf},
+};
+
+/// extensions of the reads files basecallers write, optionally followed by a compression
+/// extension
+const READS_EXTENSIONS: [&str; 5] = [".fastq", ".fq", ".bam", ".sam", ".cram"];
+const COMPRESSION_EXTENSIONS: [&str; 5] = ["", ".gz", ".bgz", ".zst", ".bz2"];
+/// polls over which a reads file's size must stay the same before it is read, so that a
+/// basecaller pausing between writes is not mistaken for having finished the file. Files are
+/// read whole rather than tailed, as compressed and BAM files cannot be decoded while growing
+const STABLE_POLLS: usize = 2;
+
+/// how the calls of a watched directory are made and when they are settled
+#[derive(Clone, Copy, Debug)]
+pub struct WatchSettings {
+    pub criteria: CallCriteria,
+    /// bootstrap replicates estimating how reproducible each call is
+    pub bootstraps: usize,
+    pub seed: u64,
+    /// fraction of bootstrap replicates reproducing a call at which it is settled and the
+    /// sample's further reads are ignored
+    pub confidence_target: f32,
+}
+
+/// the call of a sample after reading new files
+#[derive(Debug, Clone, PartialEq)]
+pub struct CallUpdate {
+    pub sample: String,
+    pub num_files: usize,
+    pub num_reads: u64,
+    pub primer_set: String,
+    pub confidence: f32,
+    /// fraction of bootstrap replicates reproducing the call
+    pub read_agreement: f32,
+    pub settled: bool,
+}
+
+/// what a poll of the watched directory found
+#[derive(Debug, Default)]
+pub struct WatchPoll {
+    /// samples whose call or agreement changed
+    pub updates: Vec<CallUpdate>,
+    pub num_files_read: usize,
+    /// reads files still being written
+    pub num_files_pending: usize,
+}
+
+/// a reads file that has not been read yet
+struct PendingFile {
+    size: u64,
+    /// polls since the size last changed
+    stable_polls: usize,
+    /// size at which reading the file failed, it is retried once the size changes
+    failed_size: Option<u64>,
+}
+
+struct WatchedSample<K: Kmer> {
+    classifier: Classifier<K>,
+    num_files: usize,
+    last_update: Option<CallUpdate>,
+    settled: bool,
+}
+
+/// classifies the reads files appearing in a directory once a basecaller has finished writing
+/// them, each barcode subdirectory being a separate sample
+pub struct DirectoryWatch<'a, K: Kmer> {
+    index: &'a PrimerSetIndex<K>,
+    dir: PathBuf,
+    search_window: usize,
+    settings: WatchSettings,
+    /// unread reads files, read once they stop growing
+    pending_files: HashMap<PathBuf, PendingFile>,
+    /// size of each reads file when it was read
+    read_files: HashMap<PathBuf, u64>,
+    samples: BTreeMap<String, WatchedSample<K>>,
+}
+
+impl<'a, K: Kmer> DirectoryWatch<'a, K> {
+    pub fn new(
+        index: &'a PrimerSetIndex<K>,
+        dir: &Path,
+        search_window: usize,
+        settings: WatchSettings,
+    ) -> Self {
+        DirectoryWatch {
+            index,
+            dir: dir.to_path_buf(),
+            search_window,
+            settings,
+            pending_files: HashMap::new(),
+            read_files: HashMap::new(),
+            samples: BTreeMap::new(),
+        }
+    }
+
+    /// reads the files whose size stayed the same over the last `STABLE_POLLS` polls, returning
+    /// the samples whose call or agreement changed. Files of settled samples are not read
+    pub fn poll(&mut self) -> Result<WatchPoll> {
+        let mut polled = WatchPoll::default();
+        let mut updated_samples = Vec::new();
+        for (sample, path) in self.reads_files()? {
+            if self.samples.get(&sample).is_some_and(|s| s.settled) {
+                continue;
+            }
+            let size = fs::metadata(&path)?.len();
+            if let Some(read_size) = self.read_files.get_mut(&path) {
+                if size != *read_size {
+                    log::warn!(
+                        "{:?} changed after it was read, its new reads are ignored",
+                        path
+                    );
+                    *read_size = size;
+                }
+                continue;
+            }
+            let pending = self
+                .pending_files
+                .entry(path.clone())
+                .or_insert(PendingFile {
+                    size,
+                    stable_polls: 0,
+                    failed_size: None,
+                });
+            if pending.size != size {
+                pending.size = size;
+                pending.stable_polls = 0;
+            } else if pending.stable_polls < STABLE_POLLS {
+                pending.stable_polls += 1;
+            }
+            if pending.stable_polls < STABLE_POLLS {
+                polled.num_files_pending += 1;
+                continue;
+            }
+            if size == 0 || pending.failed_size == Some(size) {
+                continue;
+            }
+            log::info!("reading {:?} for sample {}", path, sample);
+            let watched = self
+                .samples
+                .entry(sample.clone())
+                .or_insert_with(|| WatchedSample {
+                    classifier: Classifier::new(self.index).search_window(self.search_window),
+                    num_files: 0,
+                    last_update: None,
+                    settled: false,
+                });
+            match read_reads_file(&watched.classifier, &path) {
+                Ok(classifier) => watched.classifier = classifier,
+                Err(error) => {
+                    log::warn!("{:#}, retrying once it changes", error);
+                    pending.failed_size = Some(size);
+                    continue;
+                }
+            }
+            watched.num_files += 1;
+            polled.num_files_read += 1;
+            self.pending_files.remove(&path);
+            self.read_files.insert(path, size);
+            if !updated_samples.contains(&sample) {
+                updated_samples.push(sample);
+            }
+        }
+        polled.updates = updated_samples
+            .into_iter()
+            .filter_map(|sample| self.update_call(&sample))
+            .collect();
+        Ok(polled)
+    }
+
+    /// true once every sample seen so far has a settled call
+    pub fn all_settled(&self) -> bool {
+        !self.samples.is_empty() && self.samples.values().all(|sample| sample.settled)
+    }
+
+    /// recalls the primer set of the sample, returning the call if it changed
+    fn update_call(&mut self, sample: &str) -> Option<CallUpdate> {
+        let settings = self.settings;
+        let watched = self.samples.get_mut(sample)?;
+        let mut result = watched.classifier.current_result(&settings.criteria);
+        result.bootstrap(settings.bootstraps, settings.seed);
+        let read_agreement = result
+            .call
+            .bootstrap
+            .as_ref()
+            .map_or(0.0, |bootstrap| bootstrap.read_agreement);
+        watched.settled =
+            result.call.unknown_reason.is_none() && read_agreement >= settings.confidence_target;
+        let update = CallUpdate {
+            sample: sample.to_string(),
+            num_files: watched.num_files,
+            num_reads: result.num_reads,
+            primer_set: result.call.primer_set,
+            confidence: result.call.confidence,
+            read_agreement,
+            settled: watched.settled,
+        };
+        let changed = watched.last_update.as_ref().map_or(true, |last| {
+            last.primer_set != update.primer_set
+                || format!("{:.2}", last.read_agreement) != format!("{:.2}", read_agreement)
+                || last.settled != update.settled
+        });
+        watched.last_update = Some(update.clone());
+        changed.then_some(update)
+    }
+
+    /// reads files directly in the directory (named after the directory) and in its
+    /// subdirectories (named after the subdirectory, e.g. barcode01), in name order
+    fn reads_files(&self) -> Result<Vec<(String, PathBuf)>> {
+        let dir_name = self.dir.file_name().map_or(String::from("reads"), |name| {
+            name.to_string_lossy().into_owned()
+        });
+        let mut files = Vec::new();
+        for path in sorted_entries(&self.dir)? {
+            if path.is_dir() {
+                let sample = path
+                    .file_name()
+                    .map_or(String::new(), |name| name.to_string_lossy().into_owned());
+                for file in sorted_entries(&path)? {
+                    if is_reads_file(&file) {
+                        files.push((sample.clone(), file));
+                    }
+                }
+            } else if is_reads_file(&path) {
+                files.push((dir_name.clone(), path));
+            }
+        }
+        Ok(files)
+    }
+}
+
+/// the classifier with the reads of the file added, leaving the original unchanged when the
+/// file cannot be read (e.g. a compressed file that is still being written)
+fn read_reads_file<K: Kmer>(classifier: &Classifier<K>, path: &Path) -> Result<Classifier<K>> {
+    let reads = File::open(path).with_context(|| anyhow!("Failed to open reads {:?}", path))?;
+    let mut classifier = classifier.clone();
+    classifier
+        .add_reads(Box::new(reads))
+        .with_context(|| anyhow!("Failed to read reads {:?}", path))?;
+    Ok(classifier)
+}
+
+fn sorted_entries(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut paths = fs::read_dir(dir)
+        .with_context(|| anyhow!("Failed to list directory {:?}", dir))?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<PathBuf>, std::io::Error>>()?;
+    paths.sort();
+    Ok(paths)
+}
+
+/// FASTQ, SAM, BAM or CRAM files, skipping hidden files such as partial downloads
+fn is_reads_file(path: &Path) -> bool {
+    let Some(name) = path.file_name().map(|name| name.to_string_lossy()) else {
+        return false;
+    };
+    path.is_file()
+        && !name.starts_with('.')
+        && READS_EXTENSIONS.iter().any(|extension| {
+            COMPRESSION_EXTENSIONS
+                .iter()
+                .any(|compression| name.ends_with(&format!("{}{}", extension, compression)))
+        })
+}
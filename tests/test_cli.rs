@@ -1,7 +1,10 @@
+// the ampseer binary is only built with the cli feature
+#![cfg(feature = "cli")]
+
 use assert_cmd::Command;
 #[cfg(test)]
 use predicates::prelude::*;
-use std::path::{Path, PathBuf};
+use std::{collections::HashMap, path::Path};
 
 fn path_to_fixtures() -> &'static Path {
     Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures"))
@@ -9,17 +12,34 @@ fn path_to_fixtures() -> &'static Path {
 fn set_cwd_to_fixtures() {
     std::env::set_current_dir(Path::new(&path_to_fixtures())).unwrap();
 }
-fn path_to_ampseer() -> PathBuf {
-    let mut bin_path = std::path::PathBuf::from(
-        std::env::var("CARGO_TARGET_DIR")
-            .unwrap_or_else(|_| concat!(env!("CARGO_MANIFEST_DIR"), "/target").to_string()),
-    );
-    #[cfg(debug_assertions)]
-    bin_path.push("debug");
-    #[cfg(not(debug_assertions))]
-    bin_path.push("release");
-    bin_path.push("ampseer");
-    bin_path
+
+/// the rows of TSV output as maps from column name to value, so that tests keep passing as
+/// columns are added
+fn tsv_rows(tsv: &[u8]) -> Vec<HashMap<String, String>> {
+    let tsv = std::str::from_utf8(tsv).expect("TSV is not UTF-8");
+    let mut lines = tsv.lines();
+    let header: Vec<&str> = lines
+        .next()
+        .expect("TSV has no header")
+        .split('\t')
+        .collect();
+    lines
+        .map(|line| {
+            header
+                .iter()
+                .map(|column| column.to_string())
+                .zip(line.split('\t').map(String::from))
+                .collect()
+        })
+        .collect()
+}
+
+/// the TSV row whose `column` is `value`
+fn tsv_row(tsv: &[u8], column: &str, value: &str) -> HashMap<String, String> {
+    tsv_rows(tsv)
+        .into_iter()
+        .find(|row| row[column] == value)
+        .unwrap_or_else(|| panic!("no row with {} {}", column, value))
 }
 
 #[test]
@@ -75,6 +95,8 @@ fn test_classify_reads_from_stdin() {
     let mut cmd = Command::cargo_bin("ampseer").unwrap();
     set_cwd_to_fixtures();
 
+    // the reads are from a single amplicon
+    cmd.arg("--min-amplicon-fraction").arg("0");
     cmd.arg("--primer-sets").arg("primer_sets/neb_vss1a.fasta");
 
     cmd.pipe_stdin("vss.fastq")
@@ -83,6 +105,77 @@ fn test_classify_reads_from_stdin() {
         .stdout(predicate::str::contains("neb_vss1a"));
 }
 
+#[test]
+fn classify_reads_from_bam() {
+    let mut cmd = Command::cargo_bin("ampseer").expect("Calling binary failed");
+    set_cwd_to_fixtures();
+
+    cmd.arg("--primer-sets")
+        .arg("primer_sets/ARTIC_v4.fasta")
+        .arg("primer_sets/neb_vss1a.fasta")
+        .arg("primer_sets/neb_vss2a.fasta");
+    cmd.arg("--reads").arg("vss2_small.bam");
+    cmd.assert().stdout(predicate::str::contains("neb_vss2a"));
+}
+
+#[test]
+fn classify_reads_from_cram() {
+    let mut cmd = Command::cargo_bin("ampseer").expect("Calling binary failed");
+    set_cwd_to_fixtures();
+
+    // the reads are from a single amplicon
+    cmd.arg("--min-amplicon-fraction").arg("0");
+    cmd.arg("--primer-sets")
+        .arg("primer_sets/ARTIC_v3.fasta")
+        .arg("primer_sets/neb_vss1a.fasta");
+    cmd.arg("--reads").arg("vss.cram");
+    cmd.assert().stdout(predicate::str::contains("neb_vss1a"));
+}
+
+#[test]
+fn classify_multi_member_gzip_reads() {
+    let mut cmd = Command::cargo_bin("ampseer").expect("Calling binary failed");
+    set_cwd_to_fixtures();
+
+    // the reads are from a single amplicon
+    cmd.arg("--min-amplicon-fraction").arg("0");
+    cmd.arg("--primer-sets")
+        .arg("primer_sets/ARTIC_v3.fasta")
+        .arg("primer_sets/neb_vss1a.fasta");
+    cmd.arg("--reads").arg("vss.fastq.gz");
+    cmd.assert().stdout(predicate::str::contains("neb_vss1a"));
+}
+
+#[test]
+fn classify_zstd_reads() {
+    let mut cmd = Command::cargo_bin("ampseer").expect("Calling binary failed");
+    set_cwd_to_fixtures();
+
+    // the reads are from a single amplicon
+    cmd.arg("--min-amplicon-fraction").arg("0");
+    cmd.arg("--primer-sets")
+        .arg("primer_sets/ARTIC_v3.fasta")
+        .arg("primer_sets/neb_vss1a.fasta");
+    cmd.arg("--reads").arg("vss.fastq.zst");
+    cmd.assert().stdout(predicate::str::contains("neb_vss1a"));
+}
+
+#[test]
+fn classify_bzip2_reads_from_stdin() {
+    let mut cmd = Command::cargo_bin("ampseer").expect("Calling binary failed");
+    set_cwd_to_fixtures();
+
+    // the reads are from a single amplicon
+    cmd.arg("--min-amplicon-fraction").arg("0");
+    cmd.arg("--primer-sets")
+        .arg("primer_sets/ARTIC_v3.fasta")
+        .arg("primer_sets/neb_vss1a.fasta");
+    cmd.pipe_stdin("vss.fastq.bz2")
+        .unwrap()
+        .assert()
+        .stdout(predicate::str::contains("neb_vss1a"));
+}
+
 #[test]
 fn non_matching_primer_sets() {
     let mut cmd = Command::cargo_bin("ampseer").expect("Calling binary failed");
@@ -105,6 +198,76 @@ fn ont_amps_find_both_orientations() {
     cmd.assert().stdout(predicate::str::contains("vss_18_28"));
 }
 
+#[test]
+fn primer_bed_with_reference() {
+    let mut cmd = Command::cargo_bin("ampseer").expect("Calling binary failed");
+    set_cwd_to_fixtures();
+
+    let report_path =
+        std::env::temp_dir().join(format!("ampseer_{}_bed_report.tsv", std::process::id()));
+    cmd.arg("--primer-sets").arg("vss_18_28.primer.bed");
+    cmd.arg("--reference").arg("vss_18_28.reference.fasta");
+    cmd.arg("--reads")
+        .arg("ont_vss_full_length_amp18rev_amp28for.fastq");
+    cmd.arg("--amplicon-report").arg(&report_path);
+    cmd.assert().stdout(predicate::str::contains("vss_18_28"));
+
+    // each read spans one amplicon from its left to its right primer
+    let report = std::fs::read(&report_path).unwrap();
+    std::fs::remove_file(&report_path).unwrap();
+    for amplicon in ["varskip-0317-1_18", "varskip-0317-1_28"] {
+        let row = tsv_row(&report, "amplicon", amplicon);
+        assert_eq!(row["primer_set"], "vss_18_28");
+        assert_eq!((&*row["left_hits"], &*row["right_hits"]), ("1", "1"));
+    }
+}
+
+#[test]
+fn primer_bed_sequences_are_oligos() {
+    let mut cmd = Command::cargo_bin("ampseer").expect("Calling binary failed");
+    set_cwd_to_fixtures();
+    // primal-scheme beds carry the oligo sequences, 5' to 3', in a 7th column
+    let bed = [
+        "NC_045512.2\t7000\t7022\tvarskip-0317-1_18_LEFT\t2\t+\tTCAACCGCTGCTTTAGGTGTTT",
+        "NC_045512.2\t7547\t7574\tvarskip-0317-1_18_RIGHT\t2\t-\tAGACATAAAAGGACCTTCTAACACCAT",
+        "NC_045512.2\t11092\t11116\tvarskip-0317-1_28_LEFT\t2\t+\tGCCTTTTTACCTTTTGCTATGGGT",
+        "NC_045512.2\t11647\t11670\tvarskip-0317-1_28_RIGHT\t2\t-\tCGGTTGAGTAAACAAAAGAGGCC",
+    ];
+    let bed_path =
+        std::env::temp_dir().join(format!("ampseer_{}_oligos.primer.bed", std::process::id()));
+    std::fs::write(&bed_path, bed.join("\n")).unwrap();
+    let report_path =
+        std::env::temp_dir().join(format!("ampseer_{}_oligos_report.tsv", std::process::id()));
+
+    cmd.arg("--primer-sets").arg(&bed_path);
+    cmd.arg("--reads")
+        .arg("ont_vss_full_length_amp18rev_amp28for.fastq");
+    cmd.arg("--amplicon-report").arg(&report_path);
+    let assert = cmd.assert();
+    std::fs::remove_file(&bed_path).unwrap();
+    assert.success();
+
+    let report = std::fs::read(&report_path).unwrap();
+    std::fs::remove_file(&report_path).unwrap();
+    for amplicon in ["varskip-0317-1_18", "varskip-0317-1_28"] {
+        let row = tsv_row(&report, "amplicon", amplicon);
+        assert_eq!((&*row["left_hits"], &*row["right_hits"]), ("1", "1"));
+    }
+}
+
+#[test]
+fn primer_bed_requires_reference() {
+    let mut cmd = Command::cargo_bin("ampseer").expect("Calling binary failed");
+    set_cwd_to_fixtures();
+
+    cmd.arg("--primer-sets").arg("vss_18_28.primer.bed");
+    cmd.arg("--reads")
+        .arg("ont_vss_full_length_amp18rev_amp28for.fastq");
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("--reference is required"));
+}
+
 #[test]
 fn differentiate_vss_from_artic_v3() {
     let mut cmd = Command::cargo_bin("ampseer").expect("Calling binary failed");
@@ -146,6 +309,34 @@ fn differentiate_vss2_from_vss1a() {
     cmd.assert().stdout(predicate::str::contains("neb_vss2a"));
 }
 
+#[test]
+fn differentiate_vss2_from_vss1a_with_kmer_size_20() {
+    let mut cmd = Command::cargo_bin("ampseer").expect("Calling binary failed");
+    set_cwd_to_fixtures();
+
+    cmd.arg("--primer-sets")
+        .arg("primer_sets/neb_vss1a.fasta")
+        .arg("primer_sets/neb_vss2a.fasta");
+    cmd.arg("--reads").arg("vss2.fastq");
+    cmd.arg("--kmer-size").arg("20");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::starts_with("\"neb_vss2a\""));
+}
+
+#[test]
+fn kmer_size_longer_than_primers_is_rejected() {
+    let mut cmd = Command::cargo_bin("ampseer").expect("Calling binary failed");
+    set_cwd_to_fixtures();
+
+    cmd.arg("--primer-sets").arg("primer_sets/ARTIC_v3.fasta");
+    cmd.arg("--reads").arg("artic_v3.fastq");
+    cmd.arg("--kmer-size").arg("24");
+    cmd.assert().failure().stderr(predicate::str::contains(
+        "k-mer size 24 is longer than the shortest primer nCoV-2019_96_RIGHT (22 nt)",
+    ));
+}
+
 #[test]
 fn vss2_within_common_primer_sets_2023() {
     let mut cmd = Command::cargo_bin("ampseer").expect("Calling binary failed");
@@ -158,26 +349,1034 @@ fn vss2_within_common_primer_sets_2023() {
     cmd.arg("--reads").arg("vss2.fastq");
     cmd.assert().stdout(predicate::str::contains("neb_vss2a"));
 }
+
 #[test]
-fn vss1_within_common_primer_sets_2023() {
+fn json_output_lists_all_primer_sets() {
+    let mut cmd = Command::cargo_bin("ampseer").expect("Calling binary failed");
     set_cwd_to_fixtures();
-    let decompress = duct::cmd!("zstd", "-d", "-c", "broad_vss1a.fastq.zstd");
 
-    let ampseer_cmd = duct::cmd!(
-        path_to_ampseer(),
-        "--primer-sets",
-        "primer_sets/ARTIC_v3.fasta",
+    cmd.arg("--primer-sets")
+        .arg("primer_sets/ARTIC_v4.fasta")
+        .arg("primer_sets/neb_vss1a.fasta")
+        .arg("primer_sets/neb_vss2a.fasta");
+    cmd.arg("--reads").arg("vss2.fastq");
+    cmd.arg("--output-format").arg("json");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains(r#""primer_set": "neb_vss2a""#))
+        .stdout(predicate::str::contains(r#""name": "ARTIC_v4""#))
+        .stdout(predicate::str::contains(r#""background_rate": 0.005"#))
+        .stdout(predicate::str::contains(r#""posterior": "#));
+}
+
+#[test]
+fn vss1a_is_called_between_vss1a_and_vss2a() {
+    let mut cmd = Command::cargo_bin("ampseer").expect("Calling binary failed");
+    set_cwd_to_fixtures();
+
+    cmd.arg("--primer-sets")
+        .arg("primer_sets/neb_vss1a.fasta")
+        .arg("primer_sets/neb_vss2a.fasta");
+    cmd.arg("--reads").arg("vss1a.fastq");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::starts_with("\"neb_vss1a\""));
+}
+
+#[test]
+fn close_posteriors_compare_unique_primers() {
+    let mut cmd = Command::cargo_bin("ampseer").expect("Calling binary failed");
+    set_cwd_to_fixtures();
+    // a hundred copies of reads from each left primer shared by the two sets, so the posteriors are
+    // close, and 6 vs 2 reads on the left primers of amplicon 48 that differ between them
+    let read = |primer: &str| {
+        format!(
+            "@r\n{}ACGTACGTACGTACGTACGT\n+\n{}\n",
+            primer,
+            "I".repeat(primer.len() + 20)
+        )
+    };
+    let unique_amplicons = ["_20_LEFT", "_48_LEFT", "_64_LEFT", "_67_LEFT"];
+    let primers = std::fs::read_to_string("primer_sets/neb_vss1a.fasta").unwrap();
+    let mut reads = String::new();
+    for record in primers.split('>').skip(1) {
+        let (name, sequence) = record.split_once('\n').unwrap();
+        if name.contains("_LEFT") && !unique_amplicons.iter().any(|unique| name.contains(unique)) {
+            reads.push_str(&read(sequence.trim()).repeat(100));
+        }
+    }
+    reads.push_str(&read("GATGCACAGCCTTGTAGTGACA").repeat(6));
+    reads.push_str(&read("CAGCCTTGTAGTGACAAAGCTTATAAA").repeat(2));
+
+    cmd.arg("--primer-sets")
+        .arg("primer_sets/neb_vss1a.fasta")
+        .arg("primer_sets/neb_vss2a.fasta");
+    cmd.arg("--output-format").arg("json");
+    cmd.write_stdin(reads);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains(r#""primer_set": "neb_vss1a""#))
+        .stdout(predicate::str::contains(r#""top_primer_set": "neb_vss1a""#))
+        .stdout(predicate::str::contains(r#""top_unique_reads": 6"#))
+        .stdout(predicate::str::contains(r#""second_unique_reads": 2"#))
+        // P(Binomial(9, 1/2) <= 6) = 466/512
+        .stdout(predicate::str::contains(
+            r#""probability_top_more": 0.91015625"#,
+        ));
+}
+
+#[test]
+fn threaded_classification_matches_single_threaded() {
+    set_cwd_to_fixtures();
+    let classify = |threads: &str| {
+        let mut cmd = Command::cargo_bin("ampseer").expect("Calling binary failed");
+        cmd.arg("--primer-sets")
+            .arg("primer_sets/ARTIC_v4.fasta")
+            .arg("primer_sets/neb_vss1a.fasta")
+            .arg("primer_sets/neb_vss2a.fasta");
+        cmd.arg("--reads").arg("vss2.fastq");
+        cmd.arg("--threads").arg(threads);
+        cmd.arg("--output-format").arg("json");
+        cmd.assert().success().get_output().stdout.clone()
+    };
+    assert_eq!(classify("4"), classify("1"));
+}
+
+#[test]
+fn index_file_matches_primer_sets() {
+    set_cwd_to_fixtures();
+    let primer_sets = [
         "primer_sets/ARTIC_v4.fasta",
-        "primer_sets/Midnight_1200.fasta",
-        "primer_sets/neb_vsl1a.fasta",
         "primer_sets/neb_vss1a.fasta",
-        "primer_sets/neb_vss2a.fasta"
+        "primer_sets/neb_vss2a.fasta",
+    ];
+    let index = std::env::temp_dir().join(format!("ampseer_{}.idx", std::process::id()));
+    let mut cmd = Command::cargo_bin("ampseer").expect("Calling binary failed");
+    cmd.arg("index").arg("--primer-sets").args(primer_sets);
+    cmd.arg("--max-mismatches")
+        .arg("1")
+        .arg("--kmer-size")
+        .arg("18");
+    cmd.arg("--output").arg(&index);
+    cmd.assert().success();
+
+    let classify = |primer_args: &[&str]| {
+        let mut cmd = Command::cargo_bin("ampseer").expect("Calling binary failed");
+        cmd.args(primer_args);
+        cmd.arg("--reads").arg("vss2.fastq");
+        cmd.arg("--output-format").arg("json");
+        cmd.assert().success().get_output().stdout.clone()
+    };
+    let mut primer_args = vec!["--primer-sets"];
+    primer_args.extend(primer_sets);
+    primer_args.extend(["--max-mismatches", "1", "--kmer-size", "18"]);
+    let from_index = classify(&["--index", index.to_str().unwrap()]);
+    assert_eq!(from_index, classify(&primer_args));
+
+    let mut cmd = Command::cargo_bin("ampseer").expect("Calling binary failed");
+    cmd.arg("--index").arg("primer_sets/neb_vss2a.fasta");
+    cmd.arg("--reads").arg("vss2.fastq");
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("is not an ampseer index file"));
+
+    // the index was built with these, they cannot be changed when it is loaded
+    for (option, value) in [("--kmer-size", "16"), ("--max-mismatches", "0")] {
+        let mut cmd = Command::cargo_bin("ampseer").expect("Calling binary failed");
+        cmd.arg("--index").arg(&index);
+        cmd.arg(option).arg(value);
+        cmd.arg("--reads").arg("vss2.fastq");
+        cmd.assert()
+            .failure()
+            .stderr(predicate::str::contains("cannot be used with"));
+    }
+    std::fs::remove_file(index).unwrap();
+}
+
+#[test]
+fn index_files_of_other_format_versions_are_rejected() {
+    set_cwd_to_fixtures();
+    let index = std::env::temp_dir().join(format!("ampseer_{}_version.idx", std::process::id()));
+    let mut cmd = Command::cargo_bin("ampseer").expect("Calling binary failed");
+    cmd.arg("index")
+        .arg("--primer-sets")
+        .arg("primer_sets/neb_vss2a.fasta");
+    cmd.arg("--output").arg(&index);
+    cmd.assert().success();
+    // the format version follows the 8 magic bytes, version 1 had no amplicon lengths
+    let mut contents = std::fs::read(&index).unwrap();
+    contents[8..12].copy_from_slice(&1u32.to_le_bytes());
+    std::fs::write(&index, contents).unwrap();
+
+    let mut cmd = Command::cargo_bin("ampseer").expect("Calling binary failed");
+    cmd.arg("--index").arg(&index);
+    cmd.arg("--reads").arg("vss2.fastq");
+    let assert = cmd.assert();
+    std::fs::remove_file(&index).unwrap();
+    assert.failure().stderr(predicate::str::contains(format!(
+        "has format version 1, this ampseer reads version {}",
+        ampseer::index_file::FORMAT_VERSION
+    )));
+}
+
+#[test]
+fn list_builtin_schemes() {
+    let mut cmd = Command::cargo_bin("ampseer").expect("Calling binary failed");
+    cmd.arg("list-schemes");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::starts_with(
+            "name\tversion\tnum_amplicons\tsource\n",
+        ))
+        .stdout(predicate::str::contains("ARTIC_v4\tV4\t99\t"))
+        .stdout(predicate::str::contains("neb_vsl1a\tVarSkip Long 1a\t25\t"));
+}
+
+#[test]
+fn builtin_schemes_with_user_primer_set() {
+    let mut cmd = Command::cargo_bin("ampseer").expect("Calling binary failed");
+    set_cwd_to_fixtures();
+
+    cmd.arg("--builtin")
+        .arg("--primer-sets")
+        .arg("vss_18_28.fasta");
+    cmd.arg("--reads").arg("vss2.fastq");
+    cmd.arg("--output-format").arg("json");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains(r#""primer_set": "neb_vss2a""#))
+        .stdout(predicate::str::contains(r#""name": "Midnight_1200""#))
+        .stdout(predicate::str::contains(r#""name": "vss_18_28""#));
+}
+
+#[test]
+fn compare_schemes_reports_shared_kmers() {
+    let mut cmd = Command::cargo_bin("ampseer").expect("Calling binary failed");
+    set_cwd_to_fixtures();
+
+    cmd.arg("compare-schemes").arg("--primer-sets").args([
+        "primer_sets/neb_vss1a.fasta",
+        "primer_sets/neb_vss2a.fasta",
+        "../../primer_sets/neb_vss2a.fasta",
+    ]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "neb_vss1a\t148\t138\t138\nneb_vss2a\t138\t148\t148",
+        ))
+        .stdout(predicate::str::contains(
+            "neb_vss1a\t7\tvarskip-0317-1_14,varskip-0317-1_20,",
+        ))
+        .stderr(predicate::str::contains(
+            "neb_vss2a and neb_vss2a share all of their primer k-mers",
+        ));
+}
+
+#[test]
+fn compare_schemes_warns_about_primer_set_subsets() {
+    let mut cmd = Command::cargo_bin("ampseer").expect("Calling binary failed");
+    set_cwd_to_fixtures();
+    // the first ten amplicons of neb_vss1a, as a later revision adding primers would contain
+    let fasta = std::fs::read_to_string("primer_sets/neb_vss1a.fasta").unwrap();
+    let first_amplicons: Vec<&str> = fasta.lines().take(40).collect();
+    let subset =
+        std::env::temp_dir().join(format!("ampseer_{}_vss1a_subset.fasta", std::process::id()));
+    std::fs::write(&subset, first_amplicons.join("\n") + "\n").unwrap();
+
+    cmd.arg("compare-schemes")
+        .arg("--primer-sets")
+        .arg(&subset)
+        .arg("primer_sets/neb_vss1a.fasta");
+    let assert = cmd.assert();
+    std::fs::remove_file(&subset).unwrap();
+    assert.success().stderr(predicate::str::contains(format!(
+        "all primer k-mers of ampseer_{}_vss1a_subset are also in neb_vss1a",
+        std::process::id()
+    )));
+}
+
+#[test]
+fn library_layout_is_inferred() {
+    set_cwd_to_fixtures();
+    let mut cmd = Command::cargo_bin("ampseer").expect("Calling binary failed");
+    cmd.arg("--primer-sets").arg("vss_18_28.fasta");
+    cmd.arg("--reads")
+        .arg("ont_vss_full_length_amp18rev_amp28for.fastq");
+    cmd.arg("--output-format").arg("json");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains(r#""library_type": "full_length""#))
+        .stdout(predicate::str::contains(
+            r#""frac_both_ends_same_amplicon": 1.0"#,
+        ))
+        .stdout(predicate::str::contains(r#""median_amplicon_length": 578"#));
+
+    let mut cmd = Command::cargo_bin("ampseer").expect("Calling binary failed");
+    cmd.arg("--primer-sets").arg("primer_sets/ARTIC_v3.fasta");
+    cmd.arg("--reads").arg("artic_v3.fastq");
+    cmd.arg("--output-format").arg("tsv");
+    let output = cmd.assert().success().get_output().stdout.clone();
+    assert_eq!(tsv_rows(&output)[0]["library_type"], "fragmented");
+}
+
+#[test]
+fn one_sided_primer_hits_are_flagged() {
+    let mut cmd = Command::cargo_bin("ampseer").expect("Calling binary failed");
+    set_cwd_to_fixtures();
+
+    // the reads are from a single amplicon
+    cmd.arg("--min-amplicon-fraction").arg("0");
+    cmd.arg("--primer-sets").arg("primer_sets/neb_vss1a.fasta");
+    cmd.arg("--reads").arg("vss.fastq");
+    cmd.arg("--output-format").arg("tsv");
+    let assert = cmd.assert().success().stderr(predicate::str::contains(
+        "1 of the primer hits of neb_vss1a are on left primers",
+    ));
+    let row = &tsv_rows(&assert.get_output().stdout)[0];
+    assert_eq!(row["frac_left_primer_hits"], "1");
+    assert_eq!(row["one_sided_primer_hits"], "true");
+    assert!(row["log_likelihood"].starts_with("-140.4"));
+    assert!(row["posterior"].starts_with("0.9764998"));
+}
+
+#[test]
+fn vss_reads_are_not_flagged_one_sided() {
+    for (primer_set, reads) in [("neb_vss1a", "vss1a.fastq"), ("neb_vss2a", "vss2.fastq")] {
+        let mut cmd = Command::cargo_bin("ampseer").expect("Calling binary failed");
+        set_cwd_to_fixtures();
+
+        cmd.arg("--primer-sets")
+            .arg(format!("primer_sets/{primer_set}.fasta"));
+        cmd.arg("--reads").arg(reads);
+        cmd.arg("--output-format").arg("json");
+        cmd.assert()
+            .success()
+            .stdout(predicate::str::contains(
+                r#""one_sided_primer_hits": false"#,
+            ))
+            .stdout(predicate::str::contains(r#""one_sided_primer_hits": true"#).not());
+    }
+}
+
+#[test]
+fn mixture_of_one_primer_set() {
+    let mut cmd = Command::cargo_bin("ampseer").expect("Calling binary failed");
+    set_cwd_to_fixtures();
+
+    cmd.arg("--builtin");
+    cmd.arg("--reads").arg("vss2.fastq");
+    cmd.arg("--mixture");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("mixture: neb_vss2a 100%"));
+}
+
+#[test]
+fn mixture_of_two_primer_sets() {
+    let mut cmd = Command::cargo_bin("ampseer").expect("Calling binary failed");
+    set_cwd_to_fixtures();
+    let mut reads = std::fs::read_to_string("artic_v3.fastq").expect("failed to read fixture");
+    reads.push('\n');
+    reads.push_str(&std::fs::read_to_string("vss1a.fastq").expect("failed to read fixture"));
+
+    cmd.arg("--primer-sets")
+        .arg("primer_sets/ARTIC_v3.fasta")
+        .arg("primer_sets/neb_vss1a.fasta");
+    cmd.arg("--mixture");
+    cmd.write_stdin(reads);
+    cmd.assert().success().stdout(
+        predicate::str::is_match("mixture: neb_vss1a [0-9]+% \\+ ARTIC_v3 [0-9]+%").unwrap(),
+    );
+}
+
+/// splits full length ONT amplicon reads into 150 base mates, as sequenced from either end of
+/// the amplicon, returning the first and second mate FASTQ records
+fn ont_amplicons_as_mates() -> (Vec<String>, Vec<String>) {
+    let fastq = std::fs::read_to_string(
+        path_to_fixtures().join("ont_vss_full_length_amp18rev_amp28for.fastq"),
+    )
+    .expect("failed to read fixture");
+    let lines: Vec<&str> = fastq.lines().collect();
+    let mut firsts = Vec::new();
+    let mut seconds = Vec::new();
+    for record in lines.chunks(4) {
+        let name = record[0].trim_start_matches('@');
+        let sequence = record[1].as_bytes();
+        let mate = ampseer::reads::reverse_complement(&sequence[sequence.len() - 150..]);
+        for (mates, number, sequence) in [
+            (&mut firsts, 1, &sequence[..150]),
+            (&mut seconds, 2, mate.as_slice()),
+        ] {
+            mates.push(format!(
+                "@{}/{}\n{}\n+\n{}\n",
+                name,
+                number,
+                String::from_utf8_lossy(sequence),
+                "I".repeat(150)
+            ));
+        }
+    }
+    (firsts, seconds)
+}
+
+#[test]
+fn paired_reads_are_counted_as_pairs() {
+    let mut cmd = Command::cargo_bin("ampseer").expect("Calling binary failed");
+    set_cwd_to_fixtures();
+    let (firsts, seconds) = ont_amplicons_as_mates();
+    let reads = std::env::temp_dir().join(format!("ampseer_{}_R1.fastq", std::process::id()));
+    let mates = std::env::temp_dir().join(format!("ampseer_{}_R2.fastq", std::process::id()));
+    std::fs::write(&reads, firsts.concat()).unwrap();
+    std::fs::write(&mates, seconds.concat()).unwrap();
+
+    cmd.arg("--primer-sets").arg("primer_sets/neb_vss1a.fasta");
+    cmd.arg("--reads").arg(&reads);
+    cmd.arg("--mate").arg(&mates);
+    cmd.arg("--search-window").arg("60");
+    cmd.arg("--output-format").arg("tsv");
+    let assert = cmd.assert();
+    std::fs::remove_file(&reads).unwrap();
+    std::fs::remove_file(&mates).unwrap();
+    let row = tsv_row(
+        &assert.success().get_output().stdout,
+        "primer_set",
+        "neb_vss1a",
+    );
+    assert_eq!(row["num_consistent_reads"], "4");
+    assert_eq!(row["num_inconsistent_reads"], "0");
+    assert_eq!(row["num_concordant_pairs"], "2");
+}
+
+#[test]
+fn interleaved_reads_are_counted_as_pairs() {
+    let mut cmd = Command::cargo_bin("ampseer").expect("Calling binary failed");
+    set_cwd_to_fixtures();
+    let (firsts, seconds) = ont_amplicons_as_mates();
+    let interleaved: String = firsts
+        .iter()
+        .zip(&seconds)
+        .map(|(first, second)| format!("{}{}", first, second))
+        .collect();
+
+    cmd.arg("--primer-sets").arg("primer_sets/neb_vss1a.fasta");
+    cmd.arg("--search-window").arg("60");
+    cmd.arg("--output-format").arg("tsv");
+    cmd.write_stdin(interleaved);
+    let output = cmd.assert().success().get_output().stdout.clone();
+    let row = tsv_row(&output, "primer_set", "neb_vss1a");
+    assert_eq!(row["num_consistent_reads"], "4");
+    assert_eq!(row["num_inconsistent_reads"], "0");
+    assert_eq!(row["num_concordant_pairs"], "2");
+}
+
+#[test]
+fn interleaved_sam_records_are_paired_by_their_flags() {
+    let (firsts, seconds) = ont_amplicons_as_mates();
+    // unaligned records named without /1 and /2, as aligners write them
+    let sam_record = |fastq: &str, flag: u16| {
+        let lines: Vec<&str> = fastq.lines().collect();
+        let name = lines[0].trim_start_matches('@');
+        format!(
+            "{}\t{}\t*\t0\t0\t*\t*\t0\t0\t{}\t{}\n",
+            &name[..name.len() - 2],
+            flag,
+            lines[1],
+            lines[3]
+        )
+    };
+    // flagged as first (0x40) and second (0x80) mates the records are pairs, otherwise they
+    // are single reads despite sharing a name
+    for (first_flag, second_flag, num_inconsistent_reads) in [(77, 141, "0"), (4, 4, "4")] {
+        let mut cmd = Command::cargo_bin("ampseer").expect("Calling binary failed");
+        set_cwd_to_fixtures();
+        let interleaved: String = firsts
+            .iter()
+            .zip(&seconds)
+            .map(|(first, second)| sam_record(first, first_flag) + &sam_record(second, second_flag))
+            .collect();
+
+        cmd.arg("--primer-sets").arg("primer_sets/neb_vss1a.fasta");
+        cmd.arg("--search-window").arg("60");
+        cmd.arg("--output-format").arg("tsv");
+        cmd.write_stdin(format!("@HD\tVN:1.6\n{}", interleaved));
+        let output = cmd.assert().success().get_output().stdout.clone();
+        let row = tsv_row(&output, "primer_set", "neb_vss1a");
+        assert_eq!(row["num_consistent_reads"], "4");
+        assert_eq!(row["num_inconsistent_reads"], num_inconsistent_reads);
+    }
+}
+
+#[test]
+fn mates_must_be_in_the_same_order() {
+    let mut cmd = Command::cargo_bin("ampseer").expect("Calling binary failed");
+    set_cwd_to_fixtures();
+    let (firsts, mut seconds) = ont_amplicons_as_mates();
+    seconds.reverse();
+    let reads =
+        std::env::temp_dir().join(format!("ampseer_{}_unordered_R1.fastq", std::process::id()));
+    let mates =
+        std::env::temp_dir().join(format!("ampseer_{}_unordered_R2.fastq", std::process::id()));
+    std::fs::write(&reads, firsts.concat()).unwrap();
+    std::fs::write(&mates, seconds.concat()).unwrap();
+
+    cmd.arg("--primer-sets").arg("primer_sets/neb_vss1a.fasta");
+    cmd.arg("--reads").arg(&reads);
+    cmd.arg("--mate").arg(&mates);
+    let assert = cmd.assert();
+    std::fs::remove_file(&reads).unwrap();
+    std::fs::remove_file(&mates).unwrap();
+    assert
+        .failure()
+        .stderr(predicate::str::contains("mates must be in the same order"));
+}
+
+#[test]
+fn batch_writes_a_row_per_sample() {
+    let mut cmd = Command::cargo_bin("ampseer").expect("Calling binary failed");
+    set_cwd_to_fixtures();
+
+    cmd.arg("batch").arg("--samplesheet").arg("samplesheet.tsv");
+    cmd.arg("--primer-sets")
+        .arg("primer_sets/ARTIC_v3.fasta")
+        .arg("primer_sets/neb_vss1a.fasta")
+        .arg("primer_sets/neb_vss2a.fasta");
+    cmd.arg("--threads").arg("3");
+    let assert = cmd.assert().success().stdout(predicate::str::starts_with(
+        "sample\tnum_reads\tcall\tconfidence\tbootstrap_read_agreement\t\
+             bootstrap_amplicon_agreement\tunknown_reason\tlibrary_type\t\
+             ARTIC_v3_num_consistent_reads\t",
+    ));
+    let output = &assert.get_output().stdout;
+    for (sample, num_reads, call) in [
+        ("vss2", "4361", "neb_vss2a"),
+        ("artic", "347", "ARTIC_v3"),
+        ("vss1a", "4238", "neb_vss1a"),
+    ] {
+        let row = tsv_row(output, "sample", sample);
+        assert_eq!((&*row["num_reads"], &*row["call"]), (num_reads, call));
+    }
+}
+
+#[test]
+fn batch_reports_bootstraps_and_likelihoods() {
+    let mut cmd = Command::cargo_bin("ampseer").expect("Calling binary failed");
+    set_cwd_to_fixtures();
+
+    cmd.arg("batch").arg("--samplesheet").arg("samplesheet.tsv");
+    cmd.arg("--primer-sets")
+        .arg("primer_sets/neb_vss1a.fasta")
+        .arg("primer_sets/neb_vss2a.fasta");
+    cmd.arg("--bootstraps").arg("10");
+    let output = cmd.assert().success().get_output().stdout.clone();
+    let row = tsv_row(&output, "sample", "vss2");
+    assert_eq!(row["call"], "neb_vss2a");
+    assert_eq!(row["bootstrap_read_agreement"], "1");
+    assert_eq!(row["neb_vss2a_posterior"], "0.9999999999999984");
+    assert!(row["neb_vss2a_log_likelihood"].parse::<f64>().is_ok());
+    assert!(row.contains_key("neb_vss2a_unique_primer_reads"));
+}
+
+#[test]
+fn batch_uses_an_index_file() {
+    set_cwd_to_fixtures();
+    let primer_sets = ["primer_sets/neb_vss1a.fasta", "primer_sets/neb_vss2a.fasta"];
+    let index = std::env::temp_dir().join(format!("ampseer_{}_batch.idx", std::process::id()));
+    let mut cmd = Command::cargo_bin("ampseer").expect("Calling binary failed");
+    cmd.arg("index").arg("--primer-sets").args(primer_sets);
+    cmd.arg("--kmer-size").arg("18");
+    cmd.arg("--output").arg(&index);
+    cmd.assert().success();
+
+    let batch = |primer_args: &[&str]| {
+        let mut cmd = Command::cargo_bin("ampseer").expect("Calling binary failed");
+        cmd.arg("batch").arg("--samplesheet").arg("samplesheet.tsv");
+        cmd.args(primer_args);
+        cmd.assert().success().get_output().stdout.clone()
+    };
+    let from_index = batch(&["--index", index.to_str().unwrap()]);
+    std::fs::remove_file(&index).unwrap();
+    let mut primer_args = vec!["--primer-sets"];
+    primer_args.extend(primer_sets);
+    primer_args.extend(["--kmer-size", "18"]);
+    assert_eq!(
+        String::from_utf8(from_index).unwrap(),
+        String::from_utf8(batch(&primer_args)).unwrap()
     );
+}
+
+#[test]
+fn batch_reports_missing_sample_reads() {
+    let mut cmd = Command::cargo_bin("ampseer").expect("Calling binary failed");
+    set_cwd_to_fixtures();
+    let samplesheet =
+        std::env::temp_dir().join(format!("ampseer_{}_samples.tsv", std::process::id()));
+    std::fs::write(&samplesheet, "missing\tmissing.fastq\n").unwrap();
 
-    let pipeline = decompress.pipe(ampseer_cmd);
+    cmd.arg("batch").arg("--samplesheet").arg(&samplesheet);
+    cmd.arg("--primer-sets").arg("primer_sets/ARTIC_v3.fasta");
+    let assert = cmd.assert();
+    std::fs::remove_file(&samplesheet).unwrap();
+    assert.failure().stderr(predicate::str::contains(
+        "Failed to classify sample missing",
+    ));
+}
+
+#[test]
+fn watch_settles_the_call_of_each_barcode() {
+    let mut cmd = Command::cargo_bin("ampseer").expect("Calling binary failed");
+    set_cwd_to_fixtures();
+    let dir = std::env::temp_dir().join(format!("ampseer_{}_watch", std::process::id()));
+    for barcode in ["barcode01", "barcode02"] {
+        std::fs::create_dir_all(dir.join(barcode)).unwrap();
+    }
+    std::fs::copy("vss2.fastq", dir.join("barcode01/vss2.fastq")).unwrap();
+    // barcode02 appears once barcode01 is settled, and is still read
+    let late_barcode = {
+        let (partial, reads) = (
+            dir.join("barcode02/.artic_v3.fastq"),
+            dir.join("barcode02/artic_v3.fastq"),
+        );
+        std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_secs(1));
+            std::fs::copy("artic_v3.fastq", &partial).unwrap();
+            std::fs::rename(&partial, &reads).unwrap();
+        })
+    };
+
+    cmd.arg("--primer-sets")
+        .arg("primer_sets/ARTIC_v3.fasta")
+        .arg("primer_sets/neb_vss2a.fasta");
+    cmd.arg("--watch").arg(&dir);
+    cmd.arg("--poll-interval").arg("0.1");
+    cmd.arg("--watch-timeout").arg("2");
+    let assert = cmd.assert();
+    late_barcode.join().unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    let updates = tsv_rows(&assert.success().get_output().stdout);
+    for (sample, num_reads, call) in [
+        ("barcode01", "4361", "neb_vss2a"),
+        ("barcode02", "347", "ARTIC_v3"),
+    ] {
+        // the last update of each sample settles its call
+        let row = updates
+            .iter()
+            .rev()
+            .find(|row| row["sample"] == sample)
+            .unwrap_or_else(|| panic!("no update for {}", sample));
+        assert_eq!((&*row["num_files"], &*row["num_reads"]), ("1", num_reads));
+        assert_eq!(row["call"], call);
+        assert_eq!(row["bootstrap_read_agreement"], "1");
+        assert_eq!(row["settled"], "true");
+    }
+}
+
+#[test]
+fn watch_stops_without_new_files() {
+    let mut cmd = Command::cargo_bin("ampseer").expect("Calling binary failed");
+    set_cwd_to_fixtures();
+    let dir = std::env::temp_dir().join(format!("ampseer_{}_empty_watch", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    cmd.arg("--primer-sets").arg("primer_sets/ARTIC_v3.fasta");
+    cmd.arg("--watch").arg(&dir);
+    cmd.arg("--poll-interval").arg("0.1");
+    cmd.arg("--watch-timeout").arg("0.5");
+    let assert = cmd.assert();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert
+        .success()
+        .stdout(predicate::str::ends_with("settled\n"))
+        .stderr(predicate::str::contains("stopping with unsettled calls"));
+}
+
+#[test]
+fn watch_rejects_negative_seconds() {
+    for option in ["--poll-interval", "--watch-timeout"] {
+        let mut cmd = Command::cargo_bin("ampseer").expect("Calling binary failed");
+        set_cwd_to_fixtures();
+
+        cmd.arg("--primer-sets").arg("primer_sets/ARTIC_v3.fasta");
+        cmd.arg("--watch").arg(".");
+        cmd.arg(option).arg("-1");
+        cmd.assert().failure().stderr(predicate::str::contains(
+            "is not a number of seconds of 0 or more",
+        ));
+    }
+}
+
+#[test]
+fn reading_stops_once_the_call_is_settled() {
+    for threads in ["1", "4"] {
+        let mut cmd = Command::cargo_bin("ampseer").expect("Calling binary failed");
+        set_cwd_to_fixtures();
+
+        cmd.arg("--primer-sets")
+            .arg("primer_sets/ARTIC_v3.fasta")
+            .arg("primer_sets/neb_vss1a.fasta")
+            .arg("primer_sets/neb_vss2a.fasta");
+        cmd.arg("--reads").arg("vss2.fastq");
+        cmd.arg("--confidence-target").arg("0.95");
+        cmd.arg("--check-every").arg("500");
+        cmd.arg("--threads").arg(threads);
+        cmd.assert()
+            .success()
+            .stdout(predicate::str::starts_with("\"neb_vss2a\", 0.99").and(
+                predicate::str::ends_with("\nreads consumed: 1000 (settled)\n"),
+            ));
+    }
+}
+
+#[test]
+fn settling_options_are_checked() {
+    for (option, value, error) in [
+        (
+            "--confidence-target",
+            "0",
+            "0 is not a fraction above 0 and at most 1",
+        ),
+        (
+            "--confidence-target",
+            "1.5",
+            "1.5 is not a fraction above 0 and at most 1",
+        ),
+        ("--check-every", "0", "0 is not in 1.."),
+    ] {
+        let mut cmd = Command::cargo_bin("ampseer").expect("Calling binary failed");
+        set_cwd_to_fixtures();
+
+        cmd.arg("--primer-sets").arg("primer_sets/neb_vss2a.fasta");
+        cmd.arg("--reads").arg("vss2.fastq");
+        cmd.arg(option).arg(value);
+        cmd.assert()
+            .failure()
+            .stderr(predicate::str::contains(error));
+    }
+}
+
+#[test]
+fn max_reads_caps_the_reads_consumed() {
+    let mut cmd = Command::cargo_bin("ampseer").expect("Calling binary failed");
+    set_cwd_to_fixtures();
+
+    cmd.arg("--primer-sets").arg("primer_sets/neb_vss2a.fasta");
+    cmd.arg("--reads").arg("vss2.fastq");
+    cmd.arg("--max-reads").arg("1000");
+    cmd.arg("--output-format").arg("tsv");
+    let output = cmd.assert().success().get_output().stdout.clone();
+    let row = &tsv_rows(&output)[0];
+    assert_eq!(row["reads_consumed"], "1000");
+    assert_eq!(row["stop_reason"], "max_reads");
+}
+
+#[test]
+fn subsample_count_classifies_that_many_reads() {
+    let mut cmd = Command::cargo_bin("ampseer").expect("Calling binary failed");
+    set_cwd_to_fixtures();
 
-    let output = pipeline.read().expect("Failed to execute pipeline");
-    assert!(output.contains("neb_vss1a"));
+    cmd.arg("--primer-sets").arg("primer_sets/neb_vss2a.fasta");
+    cmd.arg("--reads").arg("vss2.fastq");
+    cmd.arg("--subsample").arg("1000");
+    // reports the reads consumed
+    cmd.arg("--max-reads").arg("1000000");
+    cmd.arg("--output-format").arg("tsv");
+    let output = cmd.assert().success().get_output().stdout.clone();
+    let row = &tsv_rows(&output)[0];
+    assert_eq!(row["reads_consumed"], "1000");
+    assert_eq!(row["stop_reason"], "end_of_reads");
+}
+
+#[test]
+fn subsample_fraction_is_reproducible() {
+    let subsampled = |seed: &str| {
+        let mut cmd = Command::cargo_bin("ampseer").expect("Calling binary failed");
+        set_cwd_to_fixtures();
+        cmd.arg("--primer-sets").arg("primer_sets/neb_vss2a.fasta");
+        cmd.arg("--reads").arg("vss2.fastq");
+        cmd.arg("--subsample").arg("0.25");
+        cmd.arg("--seed").arg(seed);
+        cmd.arg("--output-format").arg("tsv");
+        cmd.assert().success().get_output().stdout.clone()
+    };
+    assert_eq!(subsampled("1"), subsampled("1"));
+    assert_ne!(subsampled("1"), subsampled("2"));
+}
+
+#[test]
+fn tsv_output_explains_unknown_call() {
+    let mut cmd = Command::cargo_bin("ampseer").expect("Calling binary failed");
+    set_cwd_to_fixtures();
+
+    // the reads are from a single amplicon
+    cmd.arg("--min-amplicon-fraction").arg("0");
+    cmd.arg("--primer-sets").arg("primer_sets/ARTIC_v3.fasta");
+    cmd.arg("--reads").arg("vss.fastq");
+    cmd.arg("--output-format").arg("tsv");
+    let output = cmd.assert().success().get_output().stdout.clone();
+    let row = tsv_row(&output, "primer_set", "ARTIC_v3");
+    assert_eq!(row["num_inconsistent_reads"], "200");
+    assert_eq!((&*row["call"], &*row["confidence"]), ("unknown", "0"));
+    assert!(row["unknown_reason"].starts_with("ARTIC_v3 matched 0 of read ends"));
+}
+
+#[test]
+fn bootstrap_confidence_is_reported() {
+    let mut cmd = Command::cargo_bin("ampseer").expect("Calling binary failed");
+    set_cwd_to_fixtures();
+
+    cmd.arg("--primer-sets")
+        .arg("primer_sets/ARTIC_v3.fasta")
+        .arg("primer_sets/neb_vss1a.fasta");
+    cmd.arg("--reads").arg("vss1a.fastq");
+    cmd.arg("--bootstraps").arg("50").arg("--seed").arg("1");
+    cmd.arg("--output-format").arg("json");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains(r#""replicates": 50"#))
+        .stdout(predicate::str::contains(r#""read_agreement": 1.0"#))
+        .stdout(predicate::str::contains(r#""amplicon_agreement""#));
+}
+
+#[test]
+fn amplicon_report_lists_hits_and_dropouts() {
+    let mut cmd = Command::cargo_bin("ampseer").expect("Calling binary failed");
+    set_cwd_to_fixtures();
+    let report_path = std::env::temp_dir().join(format!(
+        "ampseer_{}_amplicon_report.tsv",
+        std::process::id()
+    ));
+
+    cmd.arg("--primer-sets")
+        .arg("primer_sets/ARTIC_v3.fasta")
+        .arg("primer_sets/neb_vss1a.fasta");
+    cmd.arg("--reads").arg("vss1a.fastq");
+    cmd.arg("--amplicon-report").arg(&report_path);
+    cmd.assert().success();
+
+    let report = std::fs::read(&report_path).unwrap();
+    let row = tsv_row(&report, "amplicon", "varskip-0317-1_01");
+    assert_eq!(row["primer_set"], "neb_vss1a");
+    assert_eq!((&*row["left_hits"], &*row["right_hits"]), ("8", "9"));
+    assert_eq!(row["total_hits"], "17");
+    assert_eq!(row["left_right_ratio"], "0.8888889");
+    assert_eq!(row["dropout"], "false");
+    assert_eq!(tsv_rows(&report).len(), 74);
+}
+
+#[test]
+fn primer_kmers_shared_by_amplicons_count_for_each() {
+    let mut cmd = Command::cargo_bin("ampseer").expect("Calling binary failed");
+    set_cwd_to_fixtures();
+    // a primer of another amplicon sharing its first 16 bases with varskip-0317-1_01_LEFT
+    let mut primers = std::fs::read_to_string("primer_sets/neb_vss2a.fasta").unwrap();
+    primers.push_str(">varskip-0317-1_99_LEFT\nGGTAACAAACCAACCAAGGTTACC\n");
+    let primer_set =
+        std::env::temp_dir().join(format!("ampseer_{}_shared.fasta", std::process::id()));
+    std::fs::write(&primer_set, primers).unwrap();
+    let report_path =
+        std::env::temp_dir().join(format!("ampseer_{}_shared_report.tsv", std::process::id()));
+    let reads: String = (0..20)
+        .map(|i| {
+            format!(
+                "@shared_{}\nGGTAACAAACCAACCAACTTTCGAACGTACGTACGTACGTACGT\n+\n{}\n",
+                i,
+                "I".repeat(44)
+            )
+        })
+        .collect();
+
+    cmd.arg("--primer-sets").arg(&primer_set);
+    cmd.arg("--amplicon-report").arg(&report_path);
+    cmd.write_stdin(reads);
+    let assert = cmd.assert();
+    std::fs::remove_file(&primer_set).unwrap();
+    assert.success();
+
+    let report = std::fs::read(&report_path).unwrap();
+    std::fs::remove_file(&report_path).unwrap();
+    for amplicon in ["varskip-0317-1_01", "varskip-0317-1_99"] {
+        let row = tsv_row(&report, "amplicon", amplicon);
+        assert_eq!((&*row["left_hits"], &*row["right_hits"]), ("20", "0"));
+    }
+}
+
+#[test]
+fn incomplete_primer_set_is_not_called() {
+    let mut cmd = Command::cargo_bin("ampseer").expect("Calling binary failed");
+    set_cwd_to_fixtures();
+    // ARTIC_v3 library contaminated with a single highly amplified VarSkip amplicon
+    let mut reads = std::fs::read_to_string("artic_v3.fastq").unwrap();
+    reads.push('\n');
+    for i in 0..150 {
+        reads.push_str(&format!(
+            "@contaminant_{}\nGGTAACAAACCAACCAACTTTCGAACGTACGTACGTACGTACGT\n+\n{}\n",
+            i,
+            "I".repeat(44)
+        ));
+    }
+
+    cmd.arg("--primer-sets")
+        .arg("primer_sets/ARTIC_v3.fasta")
+        .arg("primer_sets/neb_vss2a.fasta");
+    cmd.write_stdin(reads);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::starts_with("\"ARTIC_v3\""));
+}
+
+#[test]
+fn min_amplicon_fraction_is_required() {
+    let mut cmd = Command::cargo_bin("ampseer").expect("Calling binary failed");
+    set_cwd_to_fixtures();
+
+    cmd.arg("--primer-sets").arg("primer_sets/neb_vss1a.fasta");
+    cmd.arg("--reads").arg("vss.fastq");
+    cmd.arg("--min-amplicon-fraction").arg("0.5");
+    cmd.arg("--output-format").arg("json");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains(r#""primer_set": "unknown""#))
+        .stdout(predicate::str::contains(
+            "no primer set observed at least 0.5 of its amplicons",
+        ));
+}
+
+#[test]
+fn amplicon_fractions_must_be_fractions() {
+    for option in [
+        "--min-amplicon-fraction",
+        "--min-relative-amplicon-fraction",
+    ] {
+        let mut cmd = Command::cargo_bin("ampseer").expect("Calling binary failed");
+        set_cwd_to_fixtures();
+
+        cmd.arg("--primer-sets").arg("primer_sets/neb_vss1a.fasta");
+        cmd.arg("--reads").arg("vss.fastq");
+        cmd.arg(option).arg("1.5");
+        cmd.assert().failure().stderr(predicate::str::contains(
+            "1.5 is not a fraction between 0 and 1",
+        ));
+    }
+}
+
+#[test]
+fn min_amplicon_hits_must_be_positive() {
+    let mut cmd = Command::cargo_bin("ampseer").expect("Calling binary failed");
+    set_cwd_to_fixtures();
+
+    cmd.arg("--primer-sets").arg("primer_sets/neb_vss1a.fasta");
+    cmd.arg("--reads").arg("vss.fastq");
+    cmd.arg("--min-amplicon-hits").arg("0");
+    cmd.assert().failure().stderr(predicate::str::contains(
+        "invalid value '0' for '--min-amplicon-hits",
+    ));
+}
+
+#[test]
+fn mismatched_primer_reads_are_counted() {
+    let mut cmd = Command::cargo_bin("ampseer").expect("Calling binary failed");
+    set_cwd_to_fixtures();
+    // varskip-0317-1_01_LEFT with a substitution at the 7th base
+    let reads: String = (0..20)
+        .map(|i| {
+            format!(
+                "@mismatched_{}\nGGTAACTAACCAACCAACTTTCGAACGTACGTACGTACGTACGT\n+\n{}\n",
+                i,
+                "I".repeat(44)
+            )
+        })
+        .collect();
+
+    // the reads are from a single amplicon
+    cmd.arg("--min-amplicon-fraction").arg("0");
+    cmd.arg("--primer-sets").arg("primer_sets/neb_vss2a.fasta");
+    cmd.arg("--max-mismatches").arg("1");
+    cmd.arg("--output-format").arg("json");
+    cmd.write_stdin(reads);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains(r#""primer_set": "neb_vss2a""#))
+        .stdout(predicate::str::contains(r#""num_consistent_reads": 20"#))
+        .stdout(predicate::str::contains(r#""num_mismatched_reads": 20"#));
+}
+
+#[test]
+fn mismatches_to_alt_primers_of_one_amplicon_are_not_ambiguous() {
+    let mut cmd = Command::cargo_bin("ampseer").expect("Calling binary failed");
+    set_cwd_to_fixtures();
+    // an alt primer of varskip-0317-1_01_LEFT differing at the 7th and 11th bases, the reads
+    // are one substitution from each
+    let mut primers = std::fs::read_to_string("primer_sets/neb_vss2a.fasta").unwrap();
+    primers.push_str(">varskip-0317-1_01_LEFT_alt\nGGTAACTAACGAACCAACTTTCGA\n");
+    let primer_set = std::env::temp_dir().join(format!("ampseer_{}_alt.fasta", std::process::id()));
+    std::fs::write(&primer_set, primers).unwrap();
+    let reads: String = (0..20)
+        .map(|i| {
+            format!(
+                "@mismatched_{}\nGGTAACTAACCAACCAACTTTCGAACGTACGTACGTACGTACGT\n+\n{}\n",
+                i,
+                "I".repeat(44)
+            )
+        })
+        .collect();
+
+    cmd.arg("--primer-sets").arg(&primer_set);
+    cmd.arg("--max-mismatches").arg("1");
+    cmd.arg("--output-format").arg("json");
+    cmd.write_stdin(reads);
+    let assert = cmd.assert();
+    std::fs::remove_file(&primer_set).unwrap();
+    assert
+        .success()
+        .stdout(predicate::str::contains(r#""num_mismatched_reads": 20"#))
+        .stdout(predicate::str::contains(r#""num_ambiguous_reads": 0"#));
+}
+
+#[test]
+fn search_window_finds_primers_after_adapters() {
+    let mut cmd = Command::cargo_bin("ampseer").expect("Calling binary failed");
+    set_cwd_to_fixtures();
+    // ONT style reads with 28 bases of adapter/barcode before the primer
+    let adapter = "AATGTACTTCGTTCAGTTACGTATTGCT";
+    let reads: String = std::fs::read_to_string("vss.fastq")
+        .unwrap()
+        .lines()
+        .enumerate()
+        .map(|(line_num, line)| match line_num % 4 {
+            1 => format!("{}{}\n", adapter, line),
+            3 => format!("{}{}\n", "I".repeat(adapter.len()), line),
+            _ => format!("{}\n", line),
+        })
+        .collect();
+
+    // the reads are from a single amplicon
+    cmd.arg("--min-amplicon-fraction").arg("0");
+    cmd.arg("--primer-sets").arg("primer_sets/neb_vss1a.fasta");
+    cmd.arg("--search-window").arg("60");
+    cmd.arg("--output-format").arg("json");
+    cmd.write_stdin(reads);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains(r#""primer_set": "neb_vss1a""#))
+        .stdout(predicate::str::contains(r#""28": "#));
+}
+
+#[test]
+fn vss1_within_common_primer_sets_2023() {
+    let mut cmd = Command::cargo_bin("ampseer").expect("Calling binary failed");
+    set_cwd_to_fixtures();
+    let reads = std::fs::read("vss1a.fastq").expect("failed to read fixture");
+
+    cmd.arg("--primer-sets")
+        .arg("primer_sets/ARTIC_v3.fasta")
+        .arg("primer_sets/ARTIC_v4.fasta")
+        .arg("primer_sets/Midnight_1200.fasta")
+        .arg("primer_sets/neb_vsl1a.fasta")
+        .arg("primer_sets/neb_vss1a.fasta")
+        .arg("primer_sets/neb_vss2a.fasta");
+    cmd.write_stdin(zstd::encode_all(reads.as_slice(), 0).unwrap());
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::starts_with(r#""neb_vss1a""#));
 }
 
 //TODO: add ARTICv4.1, v5, v5.1, and Vss2b
@@ -0,0 +1,373 @@
+use ampseer::{
+    kmer::Kmer13,
+    likelihood::{self, CandidateReadEnds, ReadEndCounts},
+    lookup::PrimerLookupBuilder,
+    neighbours::PrimerMatch,
+    reads::{reverse_complement, ReadsReader},
+    CallCriteria, Classifier, PrimerSetIndex,
+};
+use debruijn::{
+    kmer::{Kmer16, Kmer8},
+    Kmer,
+};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs::File,
+    path::Path,
+};
+
+fn path_to_fixtures() -> &'static Path {
+    Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures"))
+}
+
+#[test]
+fn classifier_accepts_sequences_one_at_a_time() {
+    let index = PrimerSetIndex::<Kmer16>::builder()
+        .primer_set_file(path_to_fixtures().join("primer_sets/neb_vss1a.fasta"))
+        .primer_set_file(path_to_fixtures().join("primer_sets/neb_vss2a.fasta"))
+        .build()
+        .unwrap();
+    assert_eq!(
+        index.primer_set_names().collect::<Vec<&str>>(),
+        ["neb_vss1a", "neb_vss2a"]
+    );
+
+    let mut classifier = Classifier::new(&index);
+    let reads = File::open(path_to_fixtures().join("vss2.fastq")).unwrap();
+    for sequence in ReadsReader::new(Box::new(reads)).unwrap() {
+        classifier.add_sequence(&sequence.unwrap());
+    }
+    assert_eq!(classifier.num_reads(), 4361);
+
+    let result = classifier.finish(&CallCriteria::default());
+    assert_eq!(result.call.primer_set, "neb_vss2a");
+    assert_eq!(result.primer_sets[1].num_consistent_reads, 384);
+    assert_eq!(result.closest_primer_set().unwrap().name, "neb_vss2a");
+}
+
+#[test]
+fn recounts_within_a_search_window_reproduce_the_counts() {
+    let index = PrimerSetIndex::<Kmer16>::builder()
+        .primer_set_file(path_to_fixtures().join("primer_sets/neb_vss1a.fasta"))
+        .primer_set_file(path_to_fixtures().join("primer_sets/neb_vss2a.fasta"))
+        .build()
+        .unwrap();
+    let mut classifier = Classifier::new(&index).search_window(60);
+    let reads = File::open(path_to_fixtures().join("vss2.fastq")).unwrap();
+    for sequence in ReadsReader::new(Box::new(reads)).unwrap() {
+        // primer sets may match at different offsets of the window
+        let sequence = [
+            b"AATGTACTTCGTTCAGTTACGTATTGCT".as_slice(),
+            &sequence.unwrap(),
+        ]
+        .concat();
+        classifier.add_sequence(&sequence);
+    }
+
+    let mut result = classifier.finish(&CallCriteria::default());
+    for (counted, recounted) in result
+        .primer_sets
+        .iter()
+        .zip(result.recounted_primer_sets())
+    {
+        assert_eq!(counted.num_consistent_reads, recounted.num_consistent_reads);
+        assert_eq!(
+            counted.num_inconsistent_reads,
+            recounted.num_inconsistent_reads
+        );
+        assert_eq!(counted.num_mismatched_reads, recounted.num_mismatched_reads);
+        assert_eq!(counted.num_ambiguous_reads, recounted.num_ambiguous_reads);
+        assert_eq!(
+            counted.frac_amplicons_observed,
+            recounted.frac_amplicons_observed
+        );
+        assert_eq!(
+            counted.frac_left_primer_hits,
+            recounted.frac_left_primer_hits
+        );
+    }
+    result.bootstrap(20, 0);
+    assert_eq!(result.call.primer_set, "neb_vss2a");
+    assert_eq!(result.call.bootstrap.as_ref().unwrap().read_agreement, 1.0);
+    result.bootstrap(0, 0);
+    assert!(result.call.bootstrap.is_none());
+}
+
+#[test]
+fn recounts_include_concordant_pairs() {
+    let index = PrimerSetIndex::<Kmer16>::builder()
+        .primer_set_file(path_to_fixtures().join("primer_sets/neb_vss1a.fasta"))
+        .build()
+        .unwrap();
+    let mut classifier = Classifier::new(&index).search_window(60);
+    let reads = File::open(path_to_fixtures().join("ont_vss_full_length_amp18rev_amp28for.fastq"));
+    for sequence in ReadsReader::new(Box::new(reads.unwrap())).unwrap() {
+        // 150 base mates sequenced from either end of the amplicon
+        let sequence = sequence.unwrap();
+        let mate = reverse_complement(&sequence[sequence.len() - 150..]);
+        classifier.add_pair(&sequence[..150], &mate);
+    }
+
+    let result = classifier.finish(&CallCriteria::default());
+    assert_eq!(result.primer_sets[0].num_concordant_pairs, 2);
+    assert_eq!(result.recounted_primer_sets()[0].num_concordant_pairs, 2);
+}
+
+#[test]
+fn likelihoods_match_hand_computed_posteriors() {
+    // ln B(c + 1, n - c + 1) + (m - c) ln b for each set and m ln b for the background share
+    // (n - m) ln (1 - b), so the posteriors are proportional to 1/2310, 0.01/495 and 0.0001
+    let read_ends = ReadEndCounts {
+        num_read_ends: 10,
+        num_primer_read_ends: 4,
+    };
+    let candidate = |primer_set, num_read_ends, num_primers_hit| CandidateReadEnds {
+        primer_set,
+        num_read_ends,
+        num_primers_hit,
+    };
+    let (a, b) = (candidate("a", 4, 4), candidate("b", 2, 1));
+    let scores = likelihood::likelihoods(&[a, b], read_ends, 0.1);
+    assert_eq!(scores.dispersion, 1.0);
+    assert!((scores.posterior("a").unwrap() - 3000.0 / 3833.0).abs() < 1e-12);
+    assert!((scores.posterior("b").unwrap() - 140.0 / 3833.0).abs() < 1e-12);
+    assert!((scores.background.posterior - 693.0 / 3833.0).abs() < 1e-12);
+    assert_eq!(scores.best().unwrap().primer_set, "a");
+
+    // the background does not depend on the order of the candidates
+    let reordered = likelihood::likelihoods(&[b, a], read_ends, 0.1);
+    assert_eq!(
+        reordered.background.log_likelihood,
+        scores.background.log_likelihood
+    );
+    assert_eq!(reordered.posterior("a"), scores.posterior("a"));
+
+    // two read ends per primer hit of the most likely set halve the evidence, square rooting
+    // the weights
+    let duplicated = likelihood::likelihoods(&[candidate("a", 4, 2), b], read_ends, 0.1);
+    assert_eq!(duplicated.dispersion, 2.0);
+    let weights = [3000.0_f64.sqrt(), 140.0_f64.sqrt(), 693.0_f64.sqrt()];
+    let total: f64 = weights.iter().sum();
+    assert!((duplicated.posterior("a").unwrap() - weights[0] / total).abs() < 1e-12);
+    assert!((duplicated.background.posterior - weights[2] / total).abs() < 1e-12);
+}
+
+#[test]
+fn unique_primer_probability_matches_the_binomial_tail() {
+    // P(Binomial(9, 1/2) <= 6) = (1 + 9 + 36 + 84 + 126 + 126 + 84) / 512
+    let probability = likelihood::probability_first_more_frequent(6, 2);
+    assert!((probability - 466.0 / 512.0).abs() < 1e-12);
+    assert_eq!(likelihood::probability_first_more_frequent(0, 0), 0.5);
+    // the two directions are complementary, also where Stirling's series is used
+    let (more, fewer) = (
+        likelihood::probability_first_more_frequent(300, 280),
+        likelihood::probability_first_more_frequent(280, 300),
+    );
+    assert!((more + fewer - 1.0).abs() < 1e-9);
+    assert!(more > 0.75 && more < 0.85);
+}
+
+#[test]
+fn unrelated_primer_sets_do_not_change_the_posteriors() {
+    let finish = |primer_sets: &[&str]| {
+        let mut builder = PrimerSetIndex::<Kmer16>::builder();
+        for primer_set in primer_sets {
+            builder = builder.primer_set_file(
+                path_to_fixtures().join(format!("primer_sets/{}.fasta", primer_set)),
+            );
+        }
+        let index = builder.build().unwrap();
+        let mut classifier = Classifier::new(&index);
+        let reads = File::open(path_to_fixtures().join("vss1a.fastq")).unwrap();
+        classifier.add_reads(Box::new(reads)).unwrap();
+        classifier.finish(&CallCriteria::default()).call
+    };
+    let pair = finish(&["neb_vss1a", "neb_vss2a"]);
+    let all = finish(&[
+        "ARTIC_v3",
+        "ARTIC_v4",
+        "Midnight_1200",
+        "neb_vsl1a",
+        "neb_vss1a",
+        "neb_vss2a",
+    ]);
+    assert_eq!(all.primer_set, pair.primer_set);
+    let (pair, all) = (pair.likelihoods.unwrap(), all.likelihoods.unwrap());
+    assert_eq!(all.dispersion, pair.dispersion);
+    for primer_set in ["neb_vss1a", "neb_vss2a"] {
+        let difference = all.posterior(primer_set).unwrap() - pair.posterior(primer_set).unwrap();
+        assert!(difference.abs() < 1e-9, "{} {}", primer_set, difference);
+    }
+}
+
+#[test]
+fn call_confidence_is_the_posterior_of_the_primer_set() {
+    let index = PrimerSetIndex::<Kmer16>::builder()
+        .primer_set_file(path_to_fixtures().join("primer_sets/neb_vss1a.fasta"))
+        .primer_set_file(path_to_fixtures().join("primer_sets/neb_vss2a.fasta"))
+        .build()
+        .unwrap();
+    let mut classifier = Classifier::new(&index);
+    let reads = File::open(path_to_fixtures().join("vss2.fastq")).unwrap();
+    classifier.add_reads(Box::new(reads)).unwrap();
+
+    let call = classifier.finish(&CallCriteria::default()).call;
+    let likelihoods = call.likelihoods.unwrap();
+    let posterior = likelihoods.posterior("neb_vss2a").unwrap();
+    assert!(posterior > 0.95);
+    assert_eq!(call.confidence, posterior as f32);
+    assert!(likelihoods.background.log_likelihood < likelihoods.best().unwrap().log_likelihood);
+    let total: f64 = likelihoods
+        .primer_sets
+        .iter()
+        .chain([&likelihoods.background])
+        .map(|score| score.posterior)
+        .sum();
+    assert!((total - 1.0).abs() < 1e-9);
+}
+
+#[test]
+fn index_accepts_primer_records() {
+    let mut fasta_reader = noodles::fasta::reader::Builder
+        .build_from_path(path_to_fixtures().join("vss_18_28.fasta"))
+        .unwrap();
+    let records = fasta_reader
+        .records()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    let index = PrimerSetIndex::<Kmer16>::builder()
+        .primer_set_records("vss_18_28", records)
+        .build()
+        .unwrap();
+
+    let mut classifier = Classifier::new(&index);
+    let reads =
+        File::open(path_to_fixtures().join("ont_vss_full_length_amp18rev_amp28for.fastq")).unwrap();
+    classifier.add_reads(Box::new(reads)).unwrap();
+    let result = classifier.finish(&CallCriteria::default());
+    assert_eq!(result.call.primer_set, "vss_18_28");
+}
+
+#[cfg(feature = "cli")]
+#[test]
+fn watch_reports_files_read_when_the_call_is_unchanged() {
+    use ampseer::watch::{DirectoryWatch, WatchSettings};
+    let index = PrimerSetIndex::<Kmer16>::builder()
+        .primer_set_file(path_to_fixtures().join("primer_sets/neb_vss2a.fasta"))
+        .build()
+        .unwrap();
+    let dir = std::env::temp_dir().join(format!("ampseer_{}_lib_watch", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let settings = WatchSettings {
+        criteria: CallCriteria::default(),
+        bootstraps: 10,
+        seed: 0,
+        // never settled, so every file is read
+        confidence_target: 2.0,
+    };
+    let mut watch = DirectoryWatch::new(&index, &dir, 0, settings);
+    let mut poll_until_read = |name: &str| {
+        std::fs::copy(path_to_fixtures().join("vss2.fastq"), dir.join(name)).unwrap();
+        (0..10)
+            .map(|_| watch.poll().unwrap())
+            .find(|polled| polled.num_files_read > 0)
+            .expect("file was never read")
+    };
+
+    let first = poll_until_read("first.fastq");
+    assert_eq!(first.updates.len(), 1);
+    let second = poll_until_read("second.fastq");
+    assert_eq!(second.num_files_read, 1);
+    assert!(second.updates.is_empty());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(feature = "cli")]
+#[test]
+fn watch_retries_files_that_fail_to_decode() {
+    use ampseer::watch::{DirectoryWatch, WatchSettings};
+    let index = PrimerSetIndex::<Kmer16>::builder()
+        .primer_set_file(path_to_fixtures().join("primer_sets/neb_vss1a.fasta"))
+        .build()
+        .unwrap();
+    let dir = std::env::temp_dir().join(format!("ampseer_{}_truncated_watch", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let settings = WatchSettings {
+        criteria: CallCriteria::default(),
+        bootstraps: 10,
+        seed: 0,
+        confidence_target: 0.95,
+    };
+    let mut watch = DirectoryWatch::new(&index, &dir, 0, settings);
+    let reads = std::fs::read(path_to_fixtures().join("vss.fastq.gz")).unwrap();
+    let path = dir.join("reads.fastq.gz");
+
+    // a gzip file the basecaller has not finished writing
+    std::fs::write(&path, &reads[..reads.len() / 2]).unwrap();
+    for _ in 0..5 {
+        assert_eq!(watch.poll().unwrap().num_files_read, 0);
+    }
+    std::fs::write(&path, &reads).unwrap();
+    let polled = (0..5)
+        .map(|_| watch.poll().unwrap())
+        .find(|polled| polled.num_files_read > 0)
+        .expect("file was never read");
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(polled.updates[0].num_reads, 100);
+}
+
+/// k-mer ranks around every 64th bucket boundary of the lookup's 10 base prefix, the first
+/// and last k-mers and random ones
+fn boundary_ranks(k: usize, rng: &mut StdRng) -> BTreeSet<u64> {
+    let max_rank = (1u64 << (2 * k)) - 1;
+    let bucket_width = 1u64 << (2 * k.saturating_sub(10));
+    let mut ranks = BTreeSet::from([0, 1, max_rank - 1, max_rank]);
+    for bucket in (0..=max_rank / bucket_width).step_by(64) {
+        let start = bucket * bucket_width;
+        ranks.extend([start.saturating_sub(1), start, start + 1].map(|rank| rank.min(max_rank)));
+    }
+    ranks.extend((0..200).map(|_| rng.gen_range(0..=max_rank)));
+    ranks
+}
+
+fn lookup_hits_agree_with_a_naive_scan<K: Kmer>() {
+    let mut rng = StdRng::seed_from_u64(K::k() as u64);
+    // primer sets sharing some of their k-mers
+    let primer_sets: Vec<BTreeSet<u64>> = (0..3)
+        .map(|_| {
+            boundary_ranks(K::k(), &mut rng)
+                .into_iter()
+                .filter(|_| rng.gen_bool(0.5))
+                .collect()
+        })
+        .collect();
+    let mut builder = PrimerLookupBuilder::new();
+    for ranks in &primer_sets {
+        builder.add_primer_set(ranks.iter().map(|&rank| K::from_u64(rank)), HashMap::new());
+    }
+    let lookup = builder.build();
+
+    for rank in boundary_ranks(K::k(), &mut rng) {
+        let key = K::from_u64(rank);
+        let expected: Vec<(u32, PrimerMatch<K>)> = (0..)
+            .zip(&primer_sets)
+            .filter(|(_, ranks)| ranks.contains(&rank))
+            .map(|(primer_set, _)| (primer_set, PrimerMatch::Primer(key, 0)))
+            .collect();
+        let hits: Vec<(u32, PrimerMatch<K>)> = lookup
+            .hits(key)
+            .iter()
+            .map(|hit| (hit.primer_set, hit.primer))
+            .collect();
+        assert_eq!(hits, expected, "k-mer rank {} (k = {})", rank, K::k());
+    }
+}
+
+#[test]
+fn primer_lookup_agrees_with_a_naive_scan() {
+    lookup_hits_agree_with_a_naive_scan::<Kmer16>();
+    lookup_hits_agree_with_a_naive_scan::<Kmer13>();
+    // shorter than the bucket prefix
+    lookup_hits_agree_with_a_naive_scan::<Kmer8>();
+}